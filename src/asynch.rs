@@ -0,0 +1,446 @@
+//! Async driver built on `embedded-hal-async`
+//!
+//! A full refresh can keep the busy line held for 15 seconds or more on tri-color panels.
+//! [`AsyncInky`] runs the same command sequences as [`Inky`], but awaits the busy line and
+//! delays instead of blocking the calling thread, so it can be driven from an async runtime
+//! such as tokio or embassy.
+//!
+//! An [`AsyncInky`] cannot put the controller to sleep when it is dropped, since that would
+//! mean blocking on the transport. Call [`AsyncInky::sleep`] before dropping it if the display
+//! stays awake between updates.
+
+use crate::{
+    eeprom::EEPROM,
+    inky::{Inky, Operation, Rectangle},
+    transport::{BusyWait, WaitPhase},
+};
+use anyhow::{anyhow, Result};
+use embedded_hal::digital::OutputPin;
+use embedded_hal_async::{delay::DelayNs, digital::Wait, spi::SpiDevice};
+use std::{
+    future::{poll_fn, Future},
+    ops::{Deref, DerefMut},
+    pin::pin,
+    task::Poll,
+    time::Duration,
+};
+
+#[allow(async_fn_in_trait)]
+/// The operations needed to drive an Inky display controller asynchronously
+pub trait AsyncDisplayTransport {
+    /// Send a command byte to the controller with the DC line held low
+    async fn send_command(&mut self, command: u8) -> Result<()>;
+
+    /// Send data bytes to the controller with the DC line held high
+    async fn send_data(&mut self, data: &[u8]) -> Result<()>;
+
+    /// Get whether the transport holds chip select low for a whole command and its data, which
+    /// some controllers need
+    fn holds_cs(&self) -> bool {
+        false
+    }
+
+    /// Pulse the hardware reset line of the controller
+    async fn reset(&mut self) -> Result<()>;
+
+    /// Wait until the controller releases the busy line, failing with
+    /// [`crate::transport::BusyTimeout`] if it is not released within the timeout of the wait
+    async fn wait(&mut self, wait: &BusyWait) -> Result<()>;
+
+    /// Pause for a fixed duration
+    async fn delay(&mut self, duration: Duration);
+}
+
+/// Transport using `embedded-hal-async` implementations of the SPI bus, busy pin and delay
+/// source. The DC and reset pins are plain `embedded-hal` output pins. As with
+/// [`crate::transport::HalTransport`], displays that need chip select held for a whole command
+/// and its data are refused.
+pub struct AsyncHalTransport<SPI, DC, RST, BUSY, D> {
+    spi: SPI,
+    dc: DC,
+    reset: RST,
+    busy: BUSY,
+    delay: D,
+}
+
+impl<SPI, DC, RST, BUSY, D> AsyncHalTransport<SPI, DC, RST, BUSY, D>
+where
+    SPI: SpiDevice,
+    DC: OutputPin,
+    RST: OutputPin,
+    BUSY: Wait,
+    D: DelayNs,
+{
+    // Pin hold time used when pulsing the reset line, from the inky library
+    const RESET_HOLD_MS: u32 = 100;
+
+    /// Create a transport from an SPI device, the DC, reset and busy pins, and a delay source
+    pub fn new(spi: SPI, dc: DC, reset: RST, busy: BUSY, delay: D) -> Self {
+        Self {
+            spi,
+            dc,
+            reset,
+            busy,
+            delay,
+        }
+    }
+
+    /// Give the bus, pins and delay source back
+    pub fn release(self) -> (SPI, DC, RST, BUSY, D) {
+        (self.spi, self.dc, self.reset, self.busy, self.delay)
+    }
+}
+
+impl<SPI, DC, RST, BUSY, D> AsyncDisplayTransport for AsyncHalTransport<SPI, DC, RST, BUSY, D>
+where
+    SPI: SpiDevice,
+    DC: OutputPin,
+    RST: OutputPin,
+    BUSY: Wait,
+    D: DelayNs,
+{
+    async fn send_command(&mut self, command: u8) -> Result<()> {
+        self.dc
+            .set_low()
+            .map_err(|e| anyhow!("Failed to set DC pin: {:?}", e))?;
+        self.spi
+            .write(&[command])
+            .await
+            .map_err(|e| anyhow!("Failed to write command: {:?}", e))
+    }
+
+    async fn send_data(&mut self, data: &[u8]) -> Result<()> {
+        self.dc
+            .set_high()
+            .map_err(|e| anyhow!("Failed to set DC pin: {:?}", e))?;
+        self.spi
+            .write(data)
+            .await
+            .map_err(|e| anyhow!("Failed to write data: {:?}", e))
+    }
+
+    async fn reset(&mut self) -> Result<()> {
+        self.reset
+            .set_low()
+            .map_err(|e| anyhow!("Failed to set reset pin: {:?}", e))?;
+        self.delay.delay_ms(Self::RESET_HOLD_MS).await;
+        self.reset
+            .set_high()
+            .map_err(|e| anyhow!("Failed to set reset pin: {:?}", e))?;
+        self.delay.delay_ms(Self::RESET_HOLD_MS).await;
+        Ok(())
+    }
+
+    async fn wait(&mut self, wait: &BusyWait) -> Result<()> {
+        let busy = &mut self.busy;
+        let mut released = pin!(async {
+            if wait.active_low() {
+                busy.wait_for_high().await
+            } else {
+                busy.wait_for_low().await
+            }
+        });
+        let mut timeout = pin!(delay_for(&mut self.delay, wait.timeout()));
+        poll_fn(|cx| match released.as_mut().poll(cx) {
+            Poll::Ready(result) => {
+                Poll::Ready(result.map_err(|e| anyhow!("Failed to wait for busy pin: {:?}", e)))
+            }
+            Poll::Pending => timeout
+                .as_mut()
+                .poll(cx)
+                .map(|_| Err(wait.timed_out(wait.timeout()).into())),
+        })
+        .await
+    }
+
+    async fn delay(&mut self, duration: Duration) {
+        delay_for(&mut self.delay, duration).await;
+    }
+}
+
+/// Pause for a duration with a delay source that counts in u32 microseconds, which last a
+/// little over an hour, so longer pauses take several
+async fn delay_for<D: DelayNs>(delay: &mut D, duration: Duration) {
+    let mut remaining = duration.as_micros();
+    while remaining > 0 {
+        let us = u32::try_from(remaining).unwrap_or(u32::MAX);
+        delay.delay_us(us).await;
+        remaining -= u128::from(us);
+    }
+}
+
+/// Display driven over an async transport. Drawing works the same as on [`Inky`], which this
+/// dereferences to.
+pub struct AsyncInky<T> {
+    inky: Inky<T>,
+}
+
+impl<T: AsyncDisplayTransport> AsyncInky<T> {
+    /// Create a display for the panel described by an EEPROM, driven over an async transport
+    pub async fn with_transport(eeprom: EEPROM, transport: T) -> Result<Self> {
+        let mut inky = Self {
+            inky: Inky::new(eeprom, transport)?,
+        };
+        inky.inky.check_cs(inky.inky.transport().holds_cs())?;
+        inky.reset().await?;
+        Ok(inky)
+    }
+
+    /// Reset the display, which also wakes it
+    pub async fn reset(&mut self) -> Result<()> {
+        let ops = self.inky.reset_sequence()?;
+        let result = self.run(ops).await;
+        self.inky.set_asleep(result.is_err());
+        result
+    }
+
+    /// Update the display to show the contents of the canvas
+    pub async fn update(&mut self) -> Result<()> {
+        let ops = self.inky.update_sequence()?;
+        let result = self.run(ops).await;
+        self.inky.updated(result)
+    }
+
+    /// Update the display to show the contents of a region of the canvas, using a fast
+    /// partial refresh where the controller supports one
+    pub async fn update_region(&mut self, region: Rectangle) -> Result<()> {
+        let ops = self.inky.update_region_sequence(&region)?;
+        let result = self.run(ops).await;
+        self.inky.updated(result)
+    }
+
+    /// Put the controller into deep sleep until the next update or `wake`. Does nothing if it
+    /// is asleep already
+    pub async fn sleep(&mut self) -> Result<()> {
+        if self.inky.is_asleep() {
+            return Ok(());
+        }
+        let ops = self.inky.sleep_sequence()?;
+        self.run(ops).await?;
+        self.inky.set_asleep(true);
+        Ok(())
+    }
+
+    /// Wake the controller from deep sleep. Does nothing if it is awake already
+    pub async fn wake(&mut self) -> Result<()> {
+        if !self.inky.is_asleep() {
+            return Ok(());
+        }
+        self.reset().await
+    }
+
+    /// Power off the panel drivers, leaving the controller awake. The controller is woken first
+    /// if it is asleep
+    pub async fn power_off(&mut self) -> Result<()> {
+        self.wake().await?;
+        let ops = self.inky.power_off_sequence()?;
+        self.run(ops).await
+    }
+
+    /// Wait for the display to release the busy line during a phase
+    pub async fn wait(&mut self, phase: WaitPhase) -> Result<()> {
+        let wait = self.inky.busy_wait(phase);
+        self.inky.transport_mut().wait(&wait).await
+    }
+
+    /// Run a sequence of operations against the display
+    pub async fn run(&mut self, operations: Vec<Operation>) -> Result<()> {
+        let transport = self.inky.transport_mut();
+        for operation in operations {
+            match operation {
+                Operation::Reset => transport.reset().await?,
+                Operation::Send(packet) => {
+                    if let Some(command) = packet.command() {
+                        transport.send_command(command).await?;
+                    }
+                    let data = packet.data();
+                    if !data.is_empty() {
+                        transport.send_data(&data).await?;
+                    }
+                }
+                Operation::Delay(duration) => transport.delay(duration).await,
+                Operation::Wait(wait) => transport.wait(&wait).await?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Give back the underlying display
+    pub fn into_inner(self) -> Inky<T> {
+        self.inky
+    }
+}
+
+impl<T> Deref for AsyncInky<T> {
+    type Target = Inky<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inky
+    }
+}
+
+impl<T> DerefMut for AsyncInky<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inky
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AsyncDisplayTransport, AsyncHalTransport, AsyncInky};
+    use crate::{
+        eeprom::{DisplayModel, EEPROM},
+        inky::Rectangle,
+        mock::MockTransport,
+        transport::{BusyWait, WaitPhase},
+    };
+    use anyhow::Result;
+    use embedded_hal_mock::eh1::{
+        delay::{CheckedDelay, NoopDelay, Transaction as DelayTransaction},
+        digital::{Mock as PinMock, State, Transaction as PinTransaction},
+        spi::{Mock as SpiMock, Transaction as SpiTransaction},
+    };
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_async_busy_wait() -> Result<()> {
+        let spi = SpiMock::new(&[
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write_vec(vec![0x12]),
+            SpiTransaction::transaction_end(),
+        ]);
+        let dc = PinMock::new(&[PinTransaction::set(State::Low)]);
+        let reset = PinMock::new(&[
+            PinTransaction::set(State::Low),
+            PinTransaction::set(State::High),
+        ]);
+        let busy = PinMock::new(&[PinTransaction::wait_for_state(State::Low)]);
+
+        let mut transport = AsyncHalTransport::new(spi, dc, reset, busy, NoopDelay::new());
+        transport.reset().await?;
+        transport.send_command(0x12).await?;
+        transport
+            .wait(&BusyWait::new(WaitPhase::Reset, Duration::from_secs(1)))
+            .await?;
+
+        let (mut spi, mut dc, mut reset, mut busy, _) = transport.release();
+        spi.done();
+        dc.done();
+        reset.done();
+        busy.done();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_async_long_delay() {
+        let mut spi = SpiMock::new(&[]);
+        let mut dc = PinMock::new(&[]);
+        let mut reset = PinMock::new(&[]);
+        let mut busy = PinMock::new(&[]);
+        // 5000s is more microseconds than a u32 holds, so it is split in two
+        let mut delay = CheckedDelay::new(&[
+            DelayTransaction::delay_us(u32::MAX),
+            DelayTransaction::delay_us(705_032_705),
+        ]);
+        let mut transport = AsyncHalTransport::new(
+            spi.clone(),
+            dc.clone(),
+            reset.clone(),
+            busy.clone(),
+            delay.clone(),
+        );
+        transport.delay(Duration::from_secs(5000)).await;
+
+        spi.done();
+        dc.done();
+        reset.done();
+        busy.done();
+        delay.done();
+    }
+
+    #[tokio::test]
+    async fn test_async_wait_active_low() -> Result<()> {
+        // The UC8159 holds its busy line low while working, for the reset and the wait
+        let mut busy = PinMock::new(&[
+            PinTransaction::wait_for_state(State::High),
+            PinTransaction::wait_for_state(State::High),
+        ]);
+        let mut reset = PinMock::new(&[
+            PinTransaction::set(State::Low),
+            PinTransaction::set(State::High),
+        ]);
+        let mut spi = SpiMock::new(&[]);
+        let mut dc = PinMock::new(&[]);
+        // The mocks share their expectations with their clones, so the originals are checked
+        // once the display is done with the clones
+        let transport = AsyncHalTransport::new(
+            spi.clone(),
+            dc.clone(),
+            reset.clone(),
+            busy.clone(),
+            NoopDelay::new(),
+        );
+
+        let mut inky =
+            AsyncInky::with_transport(EEPROM::for_model(DisplayModel::SevenColorUc8159), transport)
+                .await?;
+        inky.wait(WaitPhase::Update).await?;
+
+        drop(inky);
+        spi.done();
+        dc.done();
+        reset.done();
+        busy.done();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_async_hal_rejects_held_cs() {
+        let mut spi = SpiMock::new(&[]);
+        let mut dc = PinMock::new(&[]);
+        let mut reset = PinMock::new(&[]);
+        let mut busy = PinMock::new(&[]);
+        let transport = AsyncHalTransport::new(
+            spi.clone(),
+            dc.clone(),
+            reset.clone(),
+            busy.clone(),
+            NoopDelay::new(),
+        );
+
+        // The AC073TC1A is refused before anything is sent, as chip select drops between the
+        // command and its data
+        let result = AsyncInky::with_transport(
+            EEPROM::for_model(DisplayModel::SevenColor800x480Ac073Tc1A),
+            transport,
+        )
+        .await;
+        let Err(error) = result else {
+            panic!("AC073TC1A was driven over a transport that cannot hold chip select");
+        };
+        assert!(error.to_string().contains("chip select"));
+
+        spi.done();
+        dc.done();
+        reset.done();
+        busy.done();
+    }
+
+    #[tokio::test]
+    async fn test_async_update_matches_blocking() -> Result<()> {
+        let mut inky = AsyncInky::with_transport(
+            EEPROM::for_model(DisplayModel::BlackWHat),
+            MockTransport::new(),
+        )
+        .await?;
+        inky.transport_mut().clear();
+
+        inky.canvas_mut().draw(Rectangle::new((0, 0), (299, 399)));
+        inky.update().await?;
+
+        inky.transport().assert_golden(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/testdata/what_black_box.trace"
+        ))
+    }
+}
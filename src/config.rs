@@ -0,0 +1,184 @@
+//! Wiring and bus configuration for the Inky display
+//!
+//! The defaults match the Pimoroni Inky HATs on a Raspberry Pi. Boards wired differently can
+//! override them with [`InkyConfigBuilder`] or load them from a TOML file, where any key left
+//! out keeps its default:
+//!
+//! ```toml
+//! dc_pin = 5
+//! busy_pin = 6
+//! spi_clock_hz = 4000000
+//! ```
+//!
+//! Setting `cs_pin` makes the driver select the display from that GPIO pin itself, holding it
+//! low for a whole command and its data rather than leaving chip select to the SPI controller.
+
+use crate::transport::RppalTransport;
+use anyhow::{bail, Context, Result};
+use derive_builder::Builder;
+use rppal::spi::{Bus, SlaveSelect as SecondarySelect};
+use serde::{Deserialize, Serialize};
+use std::{fs::read_to_string, path::Path};
+
+#[derive(Builder, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[builder(default)]
+#[serde(default, deny_unknown_fields)]
+/// GPIO pins, SPI bus, chip select and clock speed used to talk to the display
+pub struct InkyConfig {
+    dc_pin: u8,
+    reset_pin: u8,
+    busy_pin: u8,
+    spi_bus: u8,
+    chip_select: u8,
+    spi_clock_hz: u32,
+    #[builder(setter(strip_option))]
+    cs_pin: Option<u8>,
+}
+
+impl Default for InkyConfig {
+    fn default() -> Self {
+        Self {
+            dc_pin: RppalTransport::DC_PIN,
+            reset_pin: RppalTransport::RESET_PIN,
+            busy_pin: RppalTransport::BUSY_PIN,
+            spi_bus: 0,
+            chip_select: 0,
+            spi_clock_hz: RppalTransport::SPI_CLOCK_HZ,
+            cs_pin: None,
+        }
+    }
+}
+
+impl InkyConfig {
+    /// Parse a configuration from a TOML string
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        toml::from_str(toml).context("Invalid Inky configuration")
+    }
+
+    /// Load a configuration from a TOML file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        Self::from_toml(
+            &read_to_string(path).context(format!("Failed to read {}", path.display()))?,
+        )
+        .context(format!("Failed to load {}", path.display()))
+    }
+
+    /// Get the GPIO pin connected to the DC line
+    pub fn dc_pin(&self) -> u8 {
+        self.dc_pin
+    }
+
+    /// Get the GPIO pin connected to the reset line
+    pub fn reset_pin(&self) -> u8 {
+        self.reset_pin
+    }
+
+    /// Get the GPIO pin connected to the busy line
+    pub fn busy_pin(&self) -> u8 {
+        self.busy_pin
+    }
+
+    /// Get the SPI bus the display is connected to
+    pub fn spi_bus(&self) -> Result<Bus> {
+        Ok(match self.spi_bus {
+            0 => Bus::Spi0,
+            1 => Bus::Spi1,
+            2 => Bus::Spi2,
+            3 => Bus::Spi3,
+            4 => Bus::Spi4,
+            5 => Bus::Spi5,
+            6 => Bus::Spi6,
+            _ => bail!("Invalid SPI bus {}", self.spi_bus),
+        })
+    }
+
+    /// Get the chip select line of the SPI bus the display is connected to
+    pub fn chip_select(&self) -> Result<SecondarySelect> {
+        Ok(match self.chip_select {
+            0 => SecondarySelect::Ss0,
+            1 => SecondarySelect::Ss1,
+            2 => SecondarySelect::Ss2,
+            3 => SecondarySelect::Ss3,
+            4 => SecondarySelect::Ss4,
+            5 => SecondarySelect::Ss5,
+            6 => SecondarySelect::Ss6,
+            7 => SecondarySelect::Ss7,
+            8 => SecondarySelect::Ss8,
+            9 => SecondarySelect::Ss9,
+            10 => SecondarySelect::Ss10,
+            11 => SecondarySelect::Ss11,
+            12 => SecondarySelect::Ss12,
+            13 => SecondarySelect::Ss13,
+            14 => SecondarySelect::Ss14,
+            15 => SecondarySelect::Ss15,
+            _ => bail!("Invalid SPI chip select {}", self.chip_select),
+        })
+    }
+
+    /// Get the SPI clock speed in Hz
+    pub fn spi_clock_hz(&self) -> u32 {
+        self.spi_clock_hz
+    }
+
+    /// Get the GPIO pin the driver uses as chip select, if it does not leave chip select to
+    /// the SPI controller
+    pub fn cs_pin(&self) -> Option<u8> {
+        self.cs_pin
+    }
+
+    /// Get a copy of the configuration where the driver selects the display from the GPIO pin
+    /// behind the configured chip select line, unless a pin is already set
+    pub fn with_manual_cs(&self) -> Result<Self> {
+        let cs_pin = match (self.cs_pin, self.spi_bus, self.chip_select) {
+            (Some(pin), _, _) => pin,
+            // CE0 and CE1 of SPI0
+            (None, 0, 0) => 8,
+            (None, 0, 1) => 7,
+            (None, bus, chip_select) => bail!(
+                "No known GPIO pin for chip select {} of SPI bus {}, set cs_pin",
+                chip_select,
+                bus
+            ),
+        };
+        Ok(Self {
+            cs_pin: Some(cs_pin),
+            ..self.clone()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InkyConfig, InkyConfigBuilder};
+    use anyhow::Result;
+    use rppal::spi::{Bus, SlaveSelect};
+
+    #[test]
+    fn test_config_from_toml() -> Result<()> {
+        let config =
+            InkyConfig::from_toml("dc_pin = 5\nchip_select = 1\nspi_clock_hz = 4000000\n")?;
+        assert_eq!(
+            config,
+            InkyConfigBuilder::default()
+                .dc_pin(5)
+                .chip_select(1)
+                .spi_clock_hz(4_000_000)
+                .build()?
+        );
+        assert_eq!(config.reset_pin(), 27);
+        assert_eq!(config.spi_bus()?, Bus::Spi0);
+        assert_eq!(config.chip_select()?, SlaveSelect::Ss1);
+
+        assert_eq!(config.cs_pin(), None);
+        assert_eq!(config.with_manual_cs()?.cs_pin(), Some(7));
+        assert_eq!(
+            InkyConfig::from_toml("cs_pin = 25")?,
+            InkyConfigBuilder::default().cs_pin(25).build()?
+        );
+
+        assert!(InkyConfig::from_toml("dc_pins = 5").is_err());
+        assert!(InkyConfig::from_toml("spi_bus = 7")?.spi_bus().is_err());
+        Ok(())
+    }
+}
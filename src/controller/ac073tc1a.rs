@@ -0,0 +1,197 @@
+//! AC073TC1A controller, used on the 800x480 seven color Inky Impression 7.3"
+//!
+//! The AC073TC1A takes the same 4 bit palette indices as the UC8159 and also holds the busy
+//! line low while it is working, but has a longer init sequence with its own commands. It
+//! needs chip select held low for a whole command and its data, which the SPI controller does
+//! not do, so the driver selects it from a GPIO pin instead.
+
+use super::{command_name, hardware_reset_sequence, send, Controller};
+use crate::{
+    eeprom::EEPROM,
+    inky::{Inky, Layout, Operation, RamWindow},
+    transport::WaitPhase,
+};
+use anyhow::{Context, Error, Result};
+use num_derive::{FromPrimitive, ToPrimitive};
+use num_traits::FromPrimitive as ConvertFromPrimitive;
+use std::time::Duration;
+
+// Approximate refresh time of the built-in waveform
+const REFRESH_TIME: Duration = Duration::from_secs(25);
+
+#[derive(ToPrimitive, FromPrimitive, Debug, Clone)]
+#[repr(u8)]
+/// Enumeration of AC073TC1A SPI commands according to the Inky Python library
+pub enum Command {
+    PanelSetting = 0x00,
+    PowerSetting = 0x01,
+    PowerOff = 0x02,
+    PowerOffSequence = 0x03,
+    PowerOn = 0x04,
+    BoosterSoftStart1 = 0x05,
+    BoosterSoftStart2 = 0x06,
+    DeepSleep = 0x07,
+    BoosterSoftStart3 = 0x08,
+    DataStartTransmission = 0x10,
+    DisplayRefresh = 0x12,
+    ImageProcess = 0x13,
+    PllControl = 0x30,
+    TemperatureSensorEnable = 0x41,
+    VcomDataIntervalSetting = 0x50,
+    TconSetting = 0x60,
+    ResolutionSetting = 0x61,
+    VcmDcSetting = 0x82,
+    VcmDcTiming = 0x84,
+    AutoGateId = 0x86,
+    CommandHeader = 0xaa,
+    CascadeSetting = 0xe0,
+    PowerSaving = 0xe3,
+    ForceTemperature = 0xe6,
+}
+
+impl TryFrom<u8> for Command {
+    type Error = Error;
+
+    /// Convert a primitive u8 value to a Command
+    fn try_from(value: u8) -> Result<Self> {
+        ConvertFromPrimitive::from_u8(value).context("Invalid value for command")
+    }
+}
+
+impl From<Command> for u8 {
+    /// Convert a command to a primitive u8 value
+    fn from(value: Command) -> Self {
+        value as u8
+    }
+}
+
+/// The AC073TC1A controller
+pub struct Ac073Tc1a;
+
+impl<T> Controller<T> for Ac073Tc1a {
+    fn layout(&self, eeprom: &EEPROM) -> Layout {
+        Layout::new(eeprom.width() as usize, eeprom.height() as usize)
+    }
+
+    fn refresh_time(&self, _inky: &Inky<T>) -> Duration {
+        REFRESH_TIME
+    }
+
+    fn busy_active_low(&self) -> bool {
+        true
+    }
+
+    fn needs_held_cs(&self) -> bool {
+        true
+    }
+
+    fn command_name(&self, command: u8) -> Option<String> {
+        command_name::<Command>(command)
+    }
+
+    fn reset(&self, inky: &Inky<T>) -> Result<Vec<Operation>> {
+        hardware_reset_sequence(inky)
+    }
+
+    fn init(&self, inky: &Inky<T>) -> Result<Vec<Operation>> {
+        let layout = inky.canvas().layout();
+        let mut resolution = (layout.width() as u16).to_be_bytes().to_vec();
+        resolution.extend_from_slice(&(layout.height() as u16).to_be_bytes());
+
+        // Register values from the inky library
+        Ok(vec![
+            send(
+                Command::CommandHeader,
+                vec![0x49, 0x55, 0x20, 0x08, 0x09, 0x18],
+            )?,
+            send(
+                Command::PowerSetting,
+                vec![0x3f, 0x00, 0x32, 0x2a, 0x0e, 0x2a],
+            )?,
+            send(Command::PanelSetting, vec![0x5f, 0x69])?,
+            send(Command::PowerOffSequence, vec![0x00, 0x54, 0x00, 0x44])?,
+            send(Command::BoosterSoftStart1, vec![0x40, 0x1f, 0x1f, 0x2c])?,
+            send(Command::BoosterSoftStart2, vec![0x6f, 0x1f, 0x16, 0x25])?,
+            send(Command::BoosterSoftStart3, vec![0x6f, 0x1f, 0x1f, 0x22])?,
+            send(Command::ImageProcess, vec![0x00, 0x04])?,
+            send(Command::PllControl, vec![0x02])?,
+            send(Command::TemperatureSensorEnable, vec![0x00])?,
+            // Border from the palette, with the data interval from the inky library
+            send(
+                Command::VcomDataIntervalSetting,
+                vec![(inky.border_color().as_seven_color_u8() << 5) | 0x1f],
+            )?,
+            send(Command::TconSetting, vec![0x02, 0x00])?,
+            send(Command::ResolutionSetting, resolution)?,
+            send(Command::VcmDcSetting, vec![0x1e])?,
+            send(Command::VcmDcTiming, vec![0x00])?,
+            send(Command::AutoGateId, vec![0x00])?,
+            send(Command::PowerSaving, vec![0x2f])?,
+            send(Command::CascadeSetting, vec![0x00])?,
+            send(Command::ForceTemperature, vec![0x00])?,
+        ])
+    }
+
+    fn upload_buffer(&self, inky: &Inky<T>, _window: &RamWindow) -> Result<Vec<Operation>> {
+        Ok(vec![send(
+            Command::DataStartTransmission,
+            inky.canvas().pack_seven_color(),
+        )?])
+    }
+
+    fn refresh(&self, inky: &Inky<T>) -> Result<Vec<Operation>> {
+        Ok(vec![
+            send(Command::PowerOn, Vec::new())?,
+            Operation::Wait(inky.busy_wait(WaitPhase::PowerOn)),
+            send(Command::DisplayRefresh, vec![0x00])?,
+            Operation::Wait(inky.busy_wait(WaitPhase::Update)),
+            send(Command::PowerOff, vec![0x00])?,
+            Operation::Wait(inky.busy_wait(WaitPhase::PowerOff)),
+        ])
+    }
+
+    fn sleep(&self, _inky: &Inky<T>) -> Result<Vec<Operation>> {
+        // The check code guards against entering deep sleep by accident
+        Ok(vec![send(Command::DeepSleep, vec![0xa5])?])
+    }
+
+    fn power_off(&self, inky: &Inky<T>) -> Result<Vec<Operation>> {
+        Ok(vec![
+            send(Command::PowerOff, vec![0x00])?,
+            Operation::Wait(inky.busy_wait(WaitPhase::PowerOff)),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        eeprom::{DisplayModel, DisplayVariant, EEPROM},
+        inky::{Color, Inky, Rectangle},
+        mock::MockTransport,
+        transport::WaitPhase,
+    };
+    use anyhow::Result;
+
+    #[test]
+    fn test_ac073tc1a_update() -> Result<()> {
+        let mut inky = Inky::with_transport(
+            EEPROM::for_model(DisplayModel::SevenColor800x480Ac073Tc1A),
+            MockTransport::for_display(DisplayVariant::Ac073Tc1A),
+        )?;
+        assert!(inky.busy_wait(WaitPhase::Update).active_low());
+
+        inky.canvas_mut()
+            .draw_color(Rectangle::new((0, 0), (479, 799)), Color::Blue);
+        inky.canvas_mut().set_pixel(1, 0, Color::Green);
+        let packed = inky.canvas().pack_seven_color();
+        assert_eq!(packed.len(), 400 * 480);
+        assert_eq!((packed[0], packed[1], packed[400]), (0x32, 0x33, 0x33));
+
+        inky.update()?;
+        inky.transport().assert_golden(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/testdata/impression_800x480_box.trace"
+        ))
+    }
+}
@@ -0,0 +1,283 @@
+//! Command sequences for the display controllers used on Inky boards
+//!
+//! Each controller implements [`Controller`], which builds the [`Operation`]s for every step
+//! of resetting and updating a display. [`Inky`] picks the controller from the display variant
+//! in the EEPROM, so supporting another controller means adding an implementation here.
+
+use crate::{
+    eeprom::{DisplayVariant, EEPROM},
+    inky::{Color, Command, Inky, Layout, Operation, RamWindow, SpiPacketBuilder},
+    transport::WaitPhase,
+};
+use anyhow::{bail, ensure, Result};
+use std::{fmt::Debug, time::Duration};
+
+pub mod ac073tc1a;
+pub mod ssd1608;
+pub mod ssd1675;
+pub mod ssd1683;
+pub mod uc8159;
+
+// Length of the temperature register read from a controller
+pub(crate) const TEMPERATURE_LEN: usize = 2;
+
+/// The command sequences of a display controller. An update runs `init`, `upload_lut`,
+/// `set_ram_window`, `upload_buffer` and `refresh` in turn, unless the controller overrides
+/// `update_window` to keep another order, and steps a controller has no use for build no
+/// operations. [`Inky`] wakes the controller before an update and puts it to `sleep` after
+/// one. Controllers that cannot write a window of their RAM upload the whole canvas whatever
+/// the window.
+pub trait Controller<T> {
+    /// Get the placement of the canvas in the controller RAM for the panel described by an
+    /// EEPROM
+    fn layout(&self, eeprom: &EEPROM) -> Layout;
+
+    /// Estimate how long a refresh of the display takes
+    fn refresh_time(&self, inky: &Inky<T>) -> Duration;
+
+    /// Get whether the controller holds the busy line low, rather than high, while working
+    fn busy_active_low(&self) -> bool {
+        false
+    }
+
+    /// Get whether the controller needs chip select held low for a whole command and its data,
+    /// rather than only for each byte
+    fn needs_held_cs(&self) -> bool {
+        false
+    }
+
+    /// Get whether the controller takes a waveform LUT set with `Inky::set_lut`
+    fn supports_custom_lut(&self) -> bool {
+        false
+    }
+
+    /// Get whether the controller has a fast waveform for partial refreshes of the panel
+    fn supports_partial(&self, _inky: &Inky<T>) -> bool {
+        false
+    }
+
+    /// Get whether the controller can show four gray levels on the panel in grayscale mode
+    fn supports_grayscale(&self, _inky: &Inky<T>) -> bool {
+        false
+    }
+
+    /// Get the name of a command byte of the controller, if it is one
+    fn command_name(&self, command: u8) -> Option<String>;
+
+    /// Build the operations that reset the controller
+    fn reset(&self, inky: &Inky<T>) -> Result<Vec<Operation>>;
+
+    /// Build the operations that configure the controller for the panel
+    fn init(&self, inky: &Inky<T>) -> Result<Vec<Operation>>;
+
+    /// Build the operations that upload the waveform LUT, or the fast one for a partial
+    /// refresh
+    fn upload_lut(&self, _inky: &Inky<T>, _partial: bool) -> Result<Vec<Operation>> {
+        Ok(Vec::new())
+    }
+
+    /// Build the operations that set the RAM area the buffers are written to
+    fn set_ram_window(&self, _inky: &Inky<T>, _window: &RamWindow) -> Result<Vec<Operation>> {
+        Ok(Vec::new())
+    }
+
+    /// Build the operations that write a window of the canvas to the controller RAM
+    fn upload_buffer(&self, inky: &Inky<T>, window: &RamWindow) -> Result<Vec<Operation>>;
+
+    /// Build the operations that refresh the panel from the controller RAM and wait for it
+    fn refresh(&self, inky: &Inky<T>) -> Result<Vec<Operation>>;
+
+    /// Build the operations that put the controller into deep sleep, where it draws the least
+    /// power and ignores commands until it is woken
+    fn sleep(&self, _inky: &Inky<T>) -> Result<Vec<Operation>> {
+        Ok(Vec::new())
+    }
+
+    /// Build the operations that wake the controller from deep sleep. Every supported
+    /// controller only leaves deep sleep through a hardware reset
+    fn wake(&self, inky: &Inky<T>) -> Result<Vec<Operation>> {
+        self.reset(inky)
+    }
+
+    /// Build the operations that power off the panel drivers, leaving the controller awake
+    /// with its RAM and settings kept
+    fn power_off(&self, _inky: &Inky<T>) -> Result<Vec<Operation>> {
+        Ok(Vec::new())
+    }
+
+    /// Build the operations that make the controller measure the panel temperature and ask
+    /// for the result, which is then read with `DisplayTransport::read_data`
+    fn measure_temperature(&self, _inky: &Inky<T>) -> Result<Vec<Operation>> {
+        bail!("The controller cannot report the panel temperature")
+    }
+
+    /// Decode a temperature in degrees Celsius read from the controller
+    fn decode_temperature(&self, _data: &[u8]) -> Result<f32> {
+        bail!("The controller cannot report the panel temperature")
+    }
+
+    /// Build the operations that update the display to show the contents of the canvas
+    fn update(&self, inky: &Inky<T>) -> Result<Vec<Operation>> {
+        self.update_window(inky, &RamWindow::full(inky.canvas().layout()), false)
+    }
+
+    /// Build the operations that write a window of the canvas to the controller RAM and
+    /// refresh the panel, with the fast waveform if `partial` is set
+    fn update_window(
+        &self,
+        inky: &Inky<T>,
+        window: &RamWindow,
+        partial: bool,
+    ) -> Result<Vec<Operation>> {
+        let mut ops = self.init(inky)?;
+        ops.extend(self.upload_lut(inky, partial)?);
+        ops.extend(self.set_ram_window(inky, window)?);
+        ops.extend(self.upload_buffer(inky, window)?);
+        ops.extend(self.refresh(inky)?);
+        Ok(ops)
+    }
+}
+
+/// Get the controller used by a display variant
+pub fn for_variant<'a, T>(variant: DisplayVariant) -> &'a dyn Controller<T> {
+    match variant {
+        DisplayVariant::What | DisplayVariant::Phat => &ssd1675::Ssd1675,
+        DisplayVariant::PhatSsd1608 => &ssd1608::Ssd1608,
+        DisplayVariant::WhatSsd1683 => &ssd1683::Ssd1683,
+        DisplayVariant::Uc8159_600x448 | DisplayVariant::Uc8159_640x400 => &uc8159::Uc8159,
+        DisplayVariant::Ac073Tc1A => &ac073tc1a::Ac073Tc1a,
+    }
+}
+
+/// Get the name of a command byte from a command enumeration
+pub(crate) fn command_name<C: TryFrom<u8> + Debug>(command: u8) -> Option<String> {
+    C::try_from(command).ok().map(|c| format!("{:?}", c))
+}
+
+/// Build an operation sending a command with data
+pub(crate) fn send<C: Into<u8>>(command: C, data: Vec<u8>) -> Result<Operation> {
+    Ok(Operation::Send(
+        SpiPacketBuilder::default()
+            .command(command)
+            .data(data)
+            .build()?,
+    ))
+}
+
+/// Build the sequence of operations that resets a controller by pulsing the reset line, then
+/// sending a soft reset
+pub(crate) fn soft_reset_sequence<T>(inky: &Inky<T>) -> Result<Vec<Operation>> {
+    Ok(vec![
+        Operation::Reset,
+        Operation::Send(
+            SpiPacketBuilder::default()
+                .command(Command::SoftReset)
+                .build()?,
+        ),
+        Operation::Wait(inky.busy_wait(WaitPhase::Reset)),
+    ])
+}
+
+/// Build the operations that make an SSD controller measure the panel temperature with its
+/// internal sensor and ask for the temperature register
+pub(crate) fn ssd_measure_temperature<T>(inky: &Inky<T>) -> Result<Vec<Operation>> {
+    Ok(vec![
+        // Internal temperature sensor
+        send(Command::TemperatureSensorControl, vec![0x80])?,
+        // Enable the clock, load the temperature and the LUT, then disable the clock again
+        send(Command::DisplayUpdateSequence, vec![0xb1])?,
+        send(Command::TriggerDisplayUpdate, Vec::new())?,
+        Operation::Wait(inky.busy_wait(WaitPhase::Temperature)),
+        send(Command::ReadTemperatureRegister, Vec::new())?,
+    ])
+}
+
+/// Decode the temperature register of an SSD controller, a 12 bit two's complement value in
+/// sixteenths of a degree Celsius held in the high bits of two bytes
+pub(crate) fn ssd_decode_temperature(data: &[u8]) -> Result<f32> {
+    ensure!(
+        data.len() == TEMPERATURE_LEN,
+        "Temperature register is {} bytes, not {}",
+        data.len(),
+        TEMPERATURE_LEN
+    );
+    let raw = i16::from_be_bytes([data[0], data[1]]) >> 4;
+    Ok(raw as f32 / 16.0)
+}
+
+/// Get the border waveform setting of an SSD1608 or SSD1683 for a border color. Each drives
+/// the border with a LUT transition rather than a fixed level, picking the LUT by color
+pub(crate) fn ssd_border_waveform(color: Color) -> u8 {
+    match color {
+        // GS transition with LUT0, the black waveform
+        Color::Black => 0b00000000,
+        // GS transition following the LUT, with LUT2, the red waveform
+        Color::Red => 0b00000110,
+        // GS transition following the LUT, with LUT3, the yellow waveform. Bit 3 is set as in
+        // the inky library
+        Color::Yellow => 0b00001111,
+        // GS transition with LUT1, the white waveform
+        _ => 0b00000001,
+    }
+}
+
+/// Build the operations that make an SSD controller power off the panel drivers by disabling
+/// its analog block and clock
+pub(crate) fn ssd_power_off_sequence<T>(inky: &Inky<T>) -> Result<Vec<Operation>> {
+    Ok(vec![
+        send(Command::DisplayUpdateSequence, vec![0x03])?,
+        send(Command::TriggerDisplayUpdate, Vec::new())?,
+        Operation::Wait(inky.busy_wait(WaitPhase::PowerOff)),
+    ])
+}
+
+/// Build the operations that put an SSD controller into deep sleep mode 1, which keeps the RAM
+pub(crate) fn ssd_sleep_sequence() -> Result<Vec<Operation>> {
+    Ok(vec![send(Command::EnterDeepSleep, vec![0x01])?])
+}
+
+/// Build the sequence of operations that resets a controller with no soft reset command, by
+/// pulsing the reset line only
+pub(crate) fn hardware_reset_sequence<T>(inky: &Inky<T>) -> Result<Vec<Operation>> {
+    Ok(vec![
+        Operation::Reset,
+        Operation::Wait(inky.busy_wait(WaitPhase::Reset)),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{for_variant, ssd_decode_temperature, Controller};
+    use crate::{eeprom::DisplayVariant, mock::MockTransport};
+    use anyhow::Result;
+
+    #[test]
+    fn test_for_variant() {
+        let controller = |variant| -> &dyn Controller<MockTransport> { for_variant(variant) };
+
+        assert!(!controller(DisplayVariant::What).busy_active_low());
+        assert!(controller(DisplayVariant::Uc8159_600x448).busy_active_low());
+        assert!(controller(DisplayVariant::Ac073Tc1A).busy_active_low());
+        // The same byte is a different command on each command set
+        assert_eq!(
+            controller(DisplayVariant::PhatSsd1608).command_name(0x12),
+            Some("SoftReset".to_string())
+        );
+        assert_eq!(
+            controller(DisplayVariant::Uc8159_640x400).command_name(0x12),
+            Some("DisplayRefresh".to_string())
+        );
+        assert_eq!(
+            controller(DisplayVariant::WhatSsd1683).command_name(0xff),
+            None
+        );
+    }
+    #[test]
+    fn test_ssd_decode_temperature() -> Result<()> {
+        assert_eq!(ssd_decode_temperature(&[0x14, 0x00])?, 20.0);
+        assert_eq!(ssd_decode_temperature(&[0x19, 0x80])?, 25.5);
+        assert_eq!(ssd_decode_temperature(&[0xfb, 0x00])?, -5.0);
+        assert!(ssd_decode_temperature(&[0x14]).is_err());
+        Ok(())
+    }
+}
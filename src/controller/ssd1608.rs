@@ -0,0 +1,289 @@
+//! SSD1608 controller, used on the 250x122 Inky pHAT
+//!
+//! The SSD1608 shares its command set with the SSD1675, but takes a shorter LUT, has no
+//! analog or digital block control, and addresses a 136 column RAM of which only 122 columns
+//! are visible.
+
+use super::{
+    command_name, soft_reset_sequence, ssd_border_waveform, ssd_power_off_sequence,
+    ssd_sleep_sequence, Controller,
+};
+use crate::{
+    eeprom::EEPROM,
+    inky::{Command, Inky, Layout, Operation, RamWindow, SpiPacketBuilder},
+    lut::frame_time,
+    transport::WaitPhase,
+};
+use anyhow::Result;
+use std::time::Duration;
+
+// Width of the display RAM, which is wider than the visible panel
+const RAM_WIDTH: usize = 136;
+// Position of the panel in the unrotated RAM
+const OFFSET: (usize, usize) = (0, 6);
+// Frame rate with the dummy line period and gate line width set by the update sequence
+const FRAME_RATE_HZ: u32 = 50;
+
+// Waveform from the inky library: 20 bytes of voltage levels, then 10 bytes holding the
+// length of each phase in frames, two phases per byte
+pub const LUT: &[u8] = &[
+    0x02, 0x02, 0x01, 0x11, 0x12, 0x12, 0x22, 0x22, 0x66, 0x69, 0x69, 0x59, 0x58, 0x99, 0x99, 0x88,
+    0x00, 0x00, 0x00, 0x00, 0xF8, 0xB4, 0x13, 0x51, 0x35, 0x51, 0x51, 0x19, 0x01, 0x00,
+];
+
+/// Count the frames a refresh using an SSD1608 LUT lasts. The LUT is not in the SSD1675
+/// format that `Lut` decodes, so only its phase lengths are read here
+fn frames(lut: &[u8]) -> u32 {
+    lut[20..]
+        .iter()
+        .map(|tp| (tp >> 4) as u32 + (tp & 0x0f) as u32)
+        .sum()
+}
+
+/// The SSD1608 controller
+pub struct Ssd1608;
+
+impl Ssd1608 {
+    /// Get the last RAM row, which is also the gate count setting, as little endian bytes
+    fn last_row(layout: &Layout) -> [u8; 2] {
+        ((layout.ram_height() - 1) as u16).to_le_bytes()
+    }
+}
+
+impl<T> Controller<T> for Ssd1608 {
+    fn layout(&self, eeprom: &EEPROM) -> Layout {
+        // The panel is mounted rotated, so RAM rows run along its long edge
+        Layout::rotated(
+            eeprom.width() as usize,
+            eeprom.height() as usize,
+            RAM_WIDTH,
+            eeprom.width() as usize,
+            OFFSET,
+        )
+    }
+
+    fn refresh_time(&self, _inky: &Inky<T>) -> Duration {
+        frame_time(frames(LUT), FRAME_RATE_HZ)
+    }
+
+    fn command_name(&self, command: u8) -> Option<String> {
+        command_name::<Command>(command)
+    }
+
+    fn reset(&self, inky: &Inky<T>) -> Result<Vec<Operation>> {
+        soft_reset_sequence(inky)
+    }
+
+    fn init(&self, inky: &Inky<T>) -> Result<Vec<Operation>> {
+        let mut gate_setting_data = Self::last_row(inky.canvas().layout()).to_vec();
+        gate_setting_data.push(0x00);
+
+        Ok(vec![
+            Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::GateSetting)
+                    .data(gate_setting_data)
+                    .build()?,
+            ),
+            Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::DummyLinePeriod)
+                    .data(vec![0x1b])
+                    .build()?,
+            ),
+            Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::GateLineWidth)
+                    .data(vec![0x0b])
+                    .build()?,
+            ),
+            Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::DataEntryMode)
+                    .data(vec![0x03])
+                    .build()?,
+            ),
+        ])
+    }
+
+    fn upload_lut(&self, _inky: &Inky<T>, _partial: bool) -> Result<Vec<Operation>> {
+        // No partial waveform is known for the SSD1608, so regions are refreshed with the full
+        // one
+        Ok(vec![Operation::Send(
+            SpiPacketBuilder::default()
+                .command(Command::SetLUT)
+                .data(LUT.to_vec())
+                .build()?,
+        )])
+    }
+
+    fn set_ram_window(&self, _inky: &Inky<T>, window: &RamWindow) -> Result<Vec<Operation>> {
+        let mut data = (window.first_row() as u16).to_le_bytes().to_vec();
+        data.extend_from_slice(&(window.last_row() as u16).to_le_bytes());
+
+        Ok(vec![
+            Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::SetRamXStartEnd)
+                    .data(vec![
+                        (window.first_col() / 8) as u8,
+                        (window.last_col() / 8) as u8,
+                    ])
+                    .build()?,
+            ),
+            Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::SetRamYStartEnd)
+                    .data(data)
+                    .build()?,
+            ),
+        ])
+    }
+
+    fn upload_buffer(&self, inky: &Inky<T>, window: &RamWindow) -> Result<Vec<Operation>> {
+        Ok(vec![
+            Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::SetRamXPointerStart)
+                    .data(vec![(window.first_col() / 8) as u8])
+                    .build()?,
+            ),
+            Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::SetRamYPointerStart)
+                    .data((window.first_row() as u16).to_le_bytes().to_vec())
+                    .build()?,
+            ),
+            Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::SetBWBuffer)
+                    .data(inky.canvas().pack_window(window))
+                    .build()?,
+            ),
+            Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::SetRYBuffer)
+                    .data(inky.canvas().pack_color_window(window))
+                    .build()?,
+            ),
+        ])
+    }
+
+    fn refresh(&self, inky: &Inky<T>) -> Result<Vec<Operation>> {
+        Ok(vec![
+            Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::DisplayUpdateSequence)
+                    .data(vec![0xc7])
+                    .build()?,
+            ),
+            Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::TriggerDisplayUpdate)
+                    .build()?,
+            ),
+            Operation::Delay(Duration::from_millis(50)),
+            Operation::Wait(inky.busy_wait(WaitPhase::Update)),
+        ])
+    }
+
+    fn sleep(&self, _inky: &Inky<T>) -> Result<Vec<Operation>> {
+        ssd_sleep_sequence()
+    }
+
+    fn update_window(
+        &self,
+        inky: &Inky<T>,
+        window: &RamWindow,
+        partial: bool,
+    ) -> Result<Vec<Operation>> {
+        // As in the inky library, the RAM window is set before the VCOM, LUT and border
+        let mut ops = self.init(inky)?;
+        ops.extend(self.set_ram_window(inky, window)?);
+        ops.push(Operation::Send(
+            SpiPacketBuilder::default()
+                .command(Command::VComRegister)
+                .data(vec![0x70])
+                .build()?,
+        ));
+        ops.extend(self.upload_lut(inky, partial)?);
+        ops.push(Operation::Send(
+            SpiPacketBuilder::default()
+                .command(Command::GSTransition)
+                .data(vec![ssd_border_waveform(inky.border_color())])
+                .build()?,
+        ));
+        ops.extend(self.upload_buffer(inky, window)?);
+        ops.extend(self.refresh(inky)?);
+        Ok(ops)
+    }
+
+    fn power_off(&self, inky: &Inky<T>) -> Result<Vec<Operation>> {
+        ssd_power_off_sequence(inky)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{frames, LUT};
+    use crate::{
+        eeprom::{DisplayModel, EEPROM},
+        inky::{Color, Command, Inky, Operation, Rectangle},
+        mock::MockTransport,
+    };
+    use anyhow::Result;
+    use std::time::Duration;
+
+    #[test]
+    fn test_ssd1608_update() -> Result<()> {
+        let mut inky = Inky::with_transport(
+            EEPROM::for_model(DisplayModel::BlackPHatSsd1608),
+            MockTransport::new(),
+        )?;
+        inky.transport_mut().clear();
+
+        // The top left pixel is the first of RAM column 129, with the 6 hidden columns after it
+        inky.canvas_mut().set_pixel(0, 0, Color::Black);
+        let bw = inky.canvas().pack();
+        assert_eq!(bw.len(), 17 * 250);
+        assert_eq!((bw[0], bw[129 * 250 / 8]), (0xff, 0xfb));
+
+        inky.update()?;
+        inky.transport().assert_golden(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/testdata/phat_ssd1608_black_pixel.trace"
+        ))
+    }
+
+    #[test]
+    fn test_ssd1608_region_uses_full_lut() -> Result<()> {
+        let inky = Inky::with_transport(
+            EEPROM::for_model(DisplayModel::BlackPHatSsd1608),
+            MockTransport::new(),
+        )?;
+        let ops = inky.update_region_sequence(&Rectangle::new((0, 0), (7, 7)))?;
+        let luts: Vec<_> = ops
+            .iter()
+            .filter_map(|op| match op {
+                Operation::Send(packet) if packet.command() == Some(Command::SetLUT as u8) => {
+                    Some(packet.data())
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(luts, vec![LUT]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ssd1608_refresh_time() -> Result<()> {
+        let inky = Inky::with_transport(
+            EEPROM::for_model(DisplayModel::BlackPHatSsd1608),
+            MockTransport::new(),
+        )?;
+        // 79 frames at 50Hz
+        assert_eq!(frames(LUT), 79);
+        assert_eq!(inky.estimated_refresh_time(), Duration::from_millis(1580));
+        Ok(())
+    }
+}
@@ -0,0 +1,278 @@
+//! SSD1675 controller, used on the 400x300 Inky wHat and the 212x104 Inky pHAT
+//!
+//! The SSD1675 has two RAM planes, one black/white and one red/yellow, and drives the panel
+//! with a waveform LUT uploaded on every update.
+
+use super::{
+    command_name, soft_reset_sequence, ssd_decode_temperature, ssd_measure_temperature,
+    ssd_power_off_sequence, ssd_sleep_sequence, Controller,
+};
+use crate::{
+    eeprom::{Color, DisplayVariant, EEPROM},
+    inky::{Color as InkyColor, Command, Inky, Layout, Operation, RamWindow, SpiPacketBuilder},
+    lut::{Lut, LutPreset, TemperatureBand, LUT_BLACK_FAST},
+    transport::WaitPhase,
+};
+use anyhow::Result;
+use std::time::Duration;
+
+/// The SSD1675 controller
+pub struct Ssd1675;
+
+impl Ssd1675 {
+    /// Get the LUT for the model, mode and temperature band of a display
+    fn lut<T>(inky: &Inky<T>) -> Vec<u8> {
+        let preset = match inky.eeprom().display_model().info().lut() {
+            _ if inky.grayscale() => LutPreset::Gray,
+            Some(LutPreset::Red) if inky.temperature_band() == TemperatureBand::Hot => {
+                LutPreset::RedHighTemp
+            }
+            preset => preset.unwrap_or(LutPreset::Black),
+        };
+        preset.bytes().to_vec()
+    }
+}
+
+impl<T> Controller<T> for Ssd1675 {
+    fn layout(&self, eeprom: &EEPROM) -> Layout {
+        let (width, height) = (eeprom.width() as usize, eeprom.height() as usize);
+        match eeprom.display_variant() {
+            // The pHAT panel is mounted rotated, so its RAM rows run along the long edge
+            DisplayVariant::Phat => {
+                Layout::rotated(width, height, height.div_ceil(8) * 8, width, (0, 0))
+            }
+            _ => Layout::new(width, height),
+        }
+    }
+
+    fn refresh_time(&self, inky: &Inky<T>) -> Duration {
+        // The presets always decode, which the lut module tests
+        let preset = Lut::try_from(Self::lut(inky).as_slice()).ok();
+        inky.lut()
+            .or(preset.as_ref())
+            .map_or(Duration::ZERO, Lut::estimated_refresh_time)
+    }
+
+    fn supports_custom_lut(&self) -> bool {
+        true
+    }
+
+    fn supports_partial(&self, inky: &Inky<T>) -> bool {
+        // The fast waveform only drives black and white
+        inky.color() == Color::Black && !inky.grayscale()
+    }
+
+    fn supports_grayscale(&self, inky: &Inky<T>) -> bool {
+        // The gray levels use the red/yellow plane, so only black panels have it free
+        inky.color() == Color::Black
+    }
+
+    fn command_name(&self, command: u8) -> Option<String> {
+        command_name::<Command>(command)
+    }
+
+    fn reset(&self, inky: &Inky<T>) -> Result<Vec<Operation>> {
+        soft_reset_sequence(inky)
+    }
+
+    fn init(&self, inky: &Inky<T>) -> Result<Vec<Operation>> {
+        let mut ops = Vec::new();
+
+        ops.push(Operation::Send(
+            SpiPacketBuilder::default()
+                .command(Command::SetAnalogBlockControl)
+                .data(vec![0x54])
+                .build()?,
+        ));
+
+        ops.push(Operation::Send(
+            SpiPacketBuilder::default()
+                .command(Command::SetDigitalBlockControl)
+                .data(vec![0x3b])
+                .build()?,
+        ));
+
+        let layout = inky.canvas().layout();
+        let mut gate_setting_data = (layout.ram_height() as u16).to_le_bytes().to_vec();
+        gate_setting_data.push(0x00);
+
+        ops.push(Operation::Send(
+            SpiPacketBuilder::default()
+                .command(Command::GateSetting)
+                .data(gate_setting_data)
+                .build()?,
+        ));
+        ops.push(Operation::Send(
+            SpiPacketBuilder::default()
+                .command(Command::GateDrivingVoltage)
+                .data(vec![0x17])
+                .build()?,
+        ));
+
+        ops.push(Operation::Send(
+            SpiPacketBuilder::default()
+                .command(Command::SourceDrivingVoltage)
+                .data(match (inky.color(), inky.eeprom().display_variant()) {
+                    // VSH and VSL levels from the inky library for colored panels. The red
+                    // pHAT keeps the default levels.
+                    (Color::Red, DisplayVariant::What) => vec![0x30, 0xAC, 0x22],
+                    (Color::Yellow, _) => vec![0x07, 0xAC, 0x32],
+                    _ => vec![0x41, 0xAC, 0x32],
+                })
+                .build()?,
+        ));
+        ops.push(Operation::Send(
+            SpiPacketBuilder::default()
+                .command(Command::DummyLinePeriod)
+                .data(vec![0x07])
+                .build()?,
+        ));
+        ops.push(Operation::Send(
+            SpiPacketBuilder::default()
+                .command(Command::GateLineWidth)
+                .data(vec![0x04])
+                .build()?,
+        ));
+        ops.push(Operation::Send(
+            SpiPacketBuilder::default()
+                .command(Command::DataEntryMode)
+                .data(vec![0x03])
+                .build()?,
+        ));
+        ops.push(Operation::Send(
+            SpiPacketBuilder::default()
+                .command(Command::VComRegister)
+                .data(vec![0x3c])
+                .build()?,
+        ));
+
+        ops.push(Operation::Send(
+            SpiPacketBuilder::default()
+                .command(Command::GSTransition)
+                .data(vec![match inky.border_color() {
+                    // GS Transition Define A + VSS + LUT0
+                    InkyColor::Black => 0b00000000,
+                    // Fix Level Define A + VSH2 + LUT3
+                    InkyColor::Red => 0b01110011,
+                    // GS Transition Define A + VSH2 + LUT3
+                    InkyColor::Yellow => 0b00110011,
+                    // GS Transition Define A + VSH2 + LUT1, for white
+                    _ => 0b00110001,
+                }])
+                .build()?,
+        ));
+
+        Ok(ops)
+    }
+
+    fn upload_lut(&self, inky: &Inky<T>, partial: bool) -> Result<Vec<Operation>> {
+        let lut = match inky.lut() {
+            _ if partial => LUT_BLACK_FAST.to_vec(),
+            Some(lut) => Vec::from(lut),
+            None => Self::lut(inky),
+        };
+
+        Ok(vec![Operation::Send(
+            SpiPacketBuilder::default()
+                .command(Command::SetLUT)
+                .data(lut)
+                .build()?,
+        )])
+    }
+
+    fn measure_temperature(&self, inky: &Inky<T>) -> Result<Vec<Operation>> {
+        ssd_measure_temperature(inky)
+    }
+
+    fn decode_temperature(&self, data: &[u8]) -> Result<f32> {
+        ssd_decode_temperature(data)
+    }
+
+    fn set_ram_window(&self, _inky: &Inky<T>, window: &RamWindow) -> Result<Vec<Operation>> {
+        let mut data = (window.first_row() as u16).to_le_bytes().to_vec();
+        // As in the inky library, the end row is one past the last row
+        data.extend_from_slice(&((window.last_row() + 1) as u16).to_le_bytes());
+
+        Ok(vec![
+            Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::SetRamXStartEnd)
+                    .data(vec![
+                        (window.first_col() / 8) as u8,
+                        (window.last_col() / 8) as u8,
+                    ])
+                    .build()?,
+            ),
+            Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::SetRamYStartEnd)
+                    .data(data)
+                    .build()?,
+            ),
+        ])
+    }
+
+    fn upload_buffer(&self, inky: &Inky<T>, window: &RamWindow) -> Result<Vec<Operation>> {
+        let mut ops = Vec::new();
+
+        let canvas = inky.canvas();
+        let (bw, ry) = if inky.grayscale() {
+            (
+                canvas.pack_window(window),
+                canvas.pack_gray_color_window(window),
+            )
+        } else {
+            (canvas.pack_window(window), canvas.pack_color_window(window))
+        };
+
+        for (command, buffer) in [(Command::SetBWBuffer, bw), (Command::SetRYBuffer, ry)] {
+            ops.push(Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::SetRamXPointerStart)
+                    .data(vec![(window.first_col() / 8) as u8])
+                    .build()?,
+            ));
+            ops.push(Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::SetRamYPointerStart)
+                    .data((window.first_row() as u16).to_le_bytes().to_vec())
+                    .build()?,
+            ));
+            ops.push(Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(command)
+                    .data(buffer)
+                    .build()?,
+            ));
+        }
+
+        Ok(ops)
+    }
+
+    fn refresh(&self, inky: &Inky<T>) -> Result<Vec<Operation>> {
+        Ok(vec![
+            Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::DisplayUpdateSequence)
+                    .data(vec![0xc7])
+                    .build()?,
+            ),
+            Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::TriggerDisplayUpdate)
+                    .build()?,
+            ),
+            // Defined by inky
+            Operation::Delay(Duration::from_secs_f32(0.05)),
+            Operation::Wait(inky.busy_wait(WaitPhase::Update)),
+        ])
+    }
+
+    fn sleep(&self, _inky: &Inky<T>) -> Result<Vec<Operation>> {
+        ssd_sleep_sequence()
+    }
+
+    fn power_off(&self, inky: &Inky<T>) -> Result<Vec<Operation>> {
+        ssd_power_off_sequence(inky)
+    }
+}
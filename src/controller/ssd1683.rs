@@ -0,0 +1,240 @@
+//! SSD1683 controller, used on the 400x300 Inky wHat since 2022
+//!
+//! The SSD1683 drives the panel with waveforms stored in its OTP memory, selected using its
+//! internal temperature sensor, so no LUT is uploaded.
+
+use super::{
+    command_name, soft_reset_sequence, ssd_border_waveform, ssd_decode_temperature,
+    ssd_measure_temperature, ssd_power_off_sequence, ssd_sleep_sequence, Controller,
+};
+use crate::{
+    eeprom::EEPROM,
+    inky::{Command, Inky, Layout, Operation, RamWindow, SpiPacketBuilder},
+    transport::WaitPhase,
+};
+use anyhow::Result;
+use std::time::Duration;
+
+/// The SSD1683 controller
+pub struct Ssd1683;
+
+impl Ssd1683 {
+    /// Get the last RAM row, which is also the gate count setting, as little endian bytes
+    fn last_row(layout: &Layout) -> [u8; 2] {
+        ((layout.ram_height() - 1) as u16).to_le_bytes()
+    }
+}
+
+impl<T> Controller<T> for Ssd1683 {
+    fn layout(&self, eeprom: &EEPROM) -> Layout {
+        Layout::new(eeprom.width() as usize, eeprom.height() as usize)
+    }
+
+    fn refresh_time(&self, _inky: &Inky<T>) -> Duration {
+        // No timing is published for the waveforms in OTP, so the default busy timeout stands
+        // in for it and updates are allowed twice that. Slower panels need a longer timeout
+        // set with `Inky::set_busy_timeout`
+        Inky::<T>::DEFAULT_BUSY_TIMEOUT
+    }
+
+    fn command_name(&self, command: u8) -> Option<String> {
+        command_name::<Command>(command)
+    }
+
+    fn reset(&self, inky: &Inky<T>) -> Result<Vec<Operation>> {
+        soft_reset_sequence(inky)
+    }
+
+    fn init(&self, inky: &Inky<T>) -> Result<Vec<Operation>> {
+        let mut gate_setting_data = Self::last_row(inky.canvas().layout()).to_vec();
+        gate_setting_data.push(0x00);
+
+        Ok(vec![
+            Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::GateSetting)
+                    .data(gate_setting_data)
+                    .build()?,
+            ),
+            Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::DataEntryMode)
+                    .data(vec![0x03])
+                    .build()?,
+            ),
+            // Use the internal temperature sensor to select the waveform
+            Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::TemperatureSensorControl)
+                    .data(vec![0x80])
+                    .build()?,
+            ),
+        ])
+    }
+
+    fn measure_temperature(&self, inky: &Inky<T>) -> Result<Vec<Operation>> {
+        ssd_measure_temperature(inky)
+    }
+
+    fn decode_temperature(&self, data: &[u8]) -> Result<f32> {
+        ssd_decode_temperature(data)
+    }
+
+    fn set_ram_window(&self, _inky: &Inky<T>, window: &RamWindow) -> Result<Vec<Operation>> {
+        let mut data = (window.first_row() as u16).to_le_bytes().to_vec();
+        data.extend_from_slice(&(window.last_row() as u16).to_le_bytes());
+
+        Ok(vec![
+            Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::SetRamXStartEnd)
+                    .data(vec![
+                        (window.first_col() / 8) as u8,
+                        (window.last_col() / 8) as u8,
+                    ])
+                    .build()?,
+            ),
+            Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::SetRamYStartEnd)
+                    .data(data)
+                    .build()?,
+            ),
+        ])
+    }
+
+    fn upload_buffer(&self, inky: &Inky<T>, window: &RamWindow) -> Result<Vec<Operation>> {
+        let mut ops = Vec::new();
+
+        for (command, buffer) in [
+            (Command::SetBWBuffer, inky.canvas().pack_window(window)),
+            (
+                Command::SetRYBuffer,
+                inky.canvas().pack_color_window(window),
+            ),
+        ] {
+            ops.push(Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::SetRamXPointerStart)
+                    .data(vec![(window.first_col() / 8) as u8])
+                    .build()?,
+            ));
+            ops.push(Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::SetRamYPointerStart)
+                    .data((window.first_row() as u16).to_le_bytes().to_vec())
+                    .build()?,
+            ));
+            ops.push(Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(command)
+                    .data(buffer)
+                    .build()?,
+            ));
+        }
+
+        Ok(ops)
+    }
+
+    fn refresh(&self, inky: &Inky<T>) -> Result<Vec<Operation>> {
+        Ok(vec![
+            // Load the temperature and waveform, then display with the power on and off
+            // around it
+            Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::DisplayUpdateSequence)
+                    .data(vec![0xf7])
+                    .build()?,
+            ),
+            Operation::Send(
+                SpiPacketBuilder::default()
+                    .command(Command::TriggerDisplayUpdate)
+                    .build()?,
+            ),
+            Operation::Delay(Duration::from_millis(50)),
+            Operation::Wait(inky.busy_wait(WaitPhase::Update)),
+        ])
+    }
+
+    fn sleep(&self, _inky: &Inky<T>) -> Result<Vec<Operation>> {
+        ssd_sleep_sequence()
+    }
+
+    fn update_window(
+        &self,
+        inky: &Inky<T>,
+        window: &RamWindow,
+        partial: bool,
+    ) -> Result<Vec<Operation>> {
+        // As in the inky library, the border is set after the RAM window
+        let mut ops = self.init(inky)?;
+        ops.extend(self.upload_lut(inky, partial)?);
+        ops.extend(self.set_ram_window(inky, window)?);
+        ops.push(Operation::Send(
+            SpiPacketBuilder::default()
+                .command(Command::GSTransition)
+                .data(vec![ssd_border_waveform(inky.border_color())])
+                .build()?,
+        ));
+        ops.extend(self.upload_buffer(inky, window)?);
+        ops.extend(self.refresh(inky)?);
+        Ok(ops)
+    }
+
+    fn power_off(&self, inky: &Inky<T>) -> Result<Vec<Operation>> {
+        ssd_power_off_sequence(inky)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        eeprom::{DisplayModel, EEPROM},
+        inky::{Color, Command, Inky, Rectangle},
+        mock::{MockTransport, TraceEvent},
+        transport::WaitPhase,
+    };
+    use anyhow::Result;
+
+    #[test]
+    fn test_ssd1683_update() -> Result<()> {
+        let mut inky = Inky::with_transport(
+            EEPROM::for_model(DisplayModel::RedWHatSsd1683),
+            MockTransport::new(),
+        )?;
+        inky.transport_mut().clear();
+
+        inky.canvas_mut().draw(Rectangle::new((0, 0), (0, 7)));
+        inky.canvas_mut()
+            .draw_color(Rectangle::new((1, 0), (1, 7)), Color::Red);
+        inky.update()?;
+
+        // The built-in waveforms are used instead of an uploaded LUT
+        assert!(!inky
+            .transport()
+            .events()
+            .contains(&TraceEvent::Command(Command::SetLUT as u8)));
+        inky.transport().assert_golden(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/testdata/what_ssd1683_red_lines.trace"
+        ))
+    }
+
+    #[test]
+    fn test_ssd1683_busy_timeout() -> Result<()> {
+        let inky = Inky::with_transport(
+            EEPROM::for_model(DisplayModel::RedWHatSsd1683),
+            MockTransport::new(),
+        )?;
+        // With no published refresh time, updates are allowed twice the default timeout
+        assert_eq!(
+            inky.estimated_refresh_time(),
+            Inky::<MockTransport>::DEFAULT_BUSY_TIMEOUT
+        );
+        assert_eq!(
+            inky.busy_timeout(WaitPhase::Update),
+            Inky::<MockTransport>::DEFAULT_BUSY_TIMEOUT * 2
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+//! UC8159 controller, used on the 600x448 and 640x400 seven color Inky Impressions
+//!
+//! The UC8159 has its own command set. It takes a single RAM plane with a 4 bit palette index
+//! per pixel, keeps its waveforms in OTP memory, and holds the busy line low while it is
+//! working. The panel has to be powered on around each refresh.
+
+use super::{command_name, hardware_reset_sequence, send, Controller};
+use crate::{
+    eeprom::{DisplayVariant, EEPROM},
+    inky::{Inky, Layout, Operation, RamWindow},
+    transport::WaitPhase,
+};
+use anyhow::{Context, Error, Result};
+use num_derive::{FromPrimitive, ToPrimitive};
+use num_traits::FromPrimitive as ConvertFromPrimitive;
+use std::time::Duration;
+
+// Approximate refresh time of the built-in waveform
+const REFRESH_TIME: Duration = Duration::from_secs(16);
+
+#[derive(ToPrimitive, FromPrimitive, Debug, Clone)]
+#[repr(u8)]
+/// Enumeration of UC8159 SPI commands according to the Inky Python library
+pub enum Command {
+    PanelSetting = 0x00,
+    PowerSetting = 0x01,
+    PowerOff = 0x02,
+    PowerOffSequence = 0x03,
+    PowerOn = 0x04,
+    BoosterSoftStart = 0x06,
+    DeepSleep = 0x07,
+    DataStartTransmission = 0x10,
+    DataStop = 0x11,
+    DisplayRefresh = 0x12,
+    ImageProcess = 0x13,
+    PllControl = 0x30,
+    TemperatureSensorCalibration = 0x40,
+    TemperatureSensorEnable = 0x41,
+    TemperatureSensorWrite = 0x42,
+    TemperatureSensorRead = 0x43,
+    VcomDataIntervalSetting = 0x50,
+    LowPowerDetection = 0x51,
+    TconSetting = 0x60,
+    ResolutionSetting = 0x61,
+    SpiFlashControl = 0x65,
+    Revision = 0x70,
+    GetStatus = 0x71,
+    AutoMeasurementVcom = 0x80,
+    ReadVcomValue = 0x81,
+    VcmDcSetting = 0x82,
+    PowerSaving = 0xe3,
+    TemperatureSensorSetting = 0xe5,
+}
+
+impl TryFrom<u8> for Command {
+    type Error = Error;
+
+    /// Convert a primitive u8 value to a Command
+    fn try_from(value: u8) -> Result<Self> {
+        ConvertFromPrimitive::from_u8(value).context("Invalid value for command")
+    }
+}
+
+impl From<Command> for u8 {
+    /// Convert a command to a primitive u8 value
+    fn from(value: Command) -> Self {
+        value as u8
+    }
+}
+
+/// The UC8159 controller
+pub struct Uc8159;
+
+impl<T> Controller<T> for Uc8159 {
+    fn layout(&self, eeprom: &EEPROM) -> Layout {
+        Layout::new(eeprom.width() as usize, eeprom.height() as usize)
+    }
+
+    fn refresh_time(&self, _inky: &Inky<T>) -> Duration {
+        REFRESH_TIME
+    }
+
+    fn busy_active_low(&self) -> bool {
+        true
+    }
+
+    fn command_name(&self, command: u8) -> Option<String> {
+        command_name::<Command>(command)
+    }
+
+    fn reset(&self, inky: &Inky<T>) -> Result<Vec<Operation>> {
+        hardware_reset_sequence(inky)
+    }
+
+    fn init(&self, inky: &Inky<T>) -> Result<Vec<Operation>> {
+        let layout = inky.canvas().layout();
+        let mut resolution = (layout.width() as u16).to_be_bytes().to_vec();
+        resolution.extend_from_slice(&(layout.height() as u16).to_be_bytes());
+        // Resolution select bits of the panel setting
+        let resolution_setting: u8 = match inky.eeprom().display_variant() {
+            DisplayVariant::Uc8159_640x400 => 0b10,
+            _ => 0b11,
+        };
+
+        Ok(vec![
+            send(Command::ResolutionSetting, resolution)?,
+            // Resolution, LUT from OTP, scan up and shift right, booster on, no soft reset
+            send(
+                Command::PanelSetting,
+                vec![(resolution_setting << 6) | 0b101111, 0x08],
+            )?,
+            // Internal DC/DC with VGH, VGL, VSH and VSL from the inky library
+            send(Command::PowerSetting, vec![0x37, 0x00, 0x23, 0x23])?,
+            send(Command::PllControl, vec![0x3c])?,
+            send(Command::TemperatureSensorEnable, vec![0x00])?,
+            send(
+                Command::VcomDataIntervalSetting,
+                vec![(inky.border_color().as_seven_color_u8() << 5) | 0x17],
+            )?,
+            send(Command::TconSetting, vec![0x22])?,
+            send(Command::SpiFlashControl, vec![0x00])?,
+            send(Command::PowerSaving, vec![0xaa])?,
+            send(Command::PowerOffSequence, vec![0x00])?,
+        ])
+    }
+
+    fn upload_buffer(&self, inky: &Inky<T>, _window: &RamWindow) -> Result<Vec<Operation>> {
+        Ok(vec![send(
+            Command::DataStartTransmission,
+            inky.canvas().pack_seven_color(),
+        )?])
+    }
+
+    fn refresh(&self, inky: &Inky<T>) -> Result<Vec<Operation>> {
+        Ok(vec![
+            send(Command::PowerOn, Vec::new())?,
+            Operation::Wait(inky.busy_wait(WaitPhase::PowerOn)),
+            send(Command::DisplayRefresh, Vec::new())?,
+            Operation::Wait(inky.busy_wait(WaitPhase::Update)),
+            send(Command::PowerOff, Vec::new())?,
+            Operation::Wait(inky.busy_wait(WaitPhase::PowerOff)),
+        ])
+    }
+
+    fn sleep(&self, _inky: &Inky<T>) -> Result<Vec<Operation>> {
+        // The check code guards against entering deep sleep by accident
+        Ok(vec![send(Command::DeepSleep, vec![0xa5])?])
+    }
+
+    fn power_off(&self, inky: &Inky<T>) -> Result<Vec<Operation>> {
+        Ok(vec![
+            send(Command::PowerOff, Vec::new())?,
+            Operation::Wait(inky.busy_wait(WaitPhase::PowerOff)),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        eeprom::{DisplayModel, DisplayVariant, EEPROM},
+        inky::{Color, Inky, Rectangle},
+        mock::MockTransport,
+        transport::WaitPhase,
+    };
+    use anyhow::Result;
+
+    #[test]
+    fn test_uc8159_update() -> Result<()> {
+        let mut inky = Inky::with_transport(
+            EEPROM::for_model(DisplayModel::SevenColorUc8159),
+            MockTransport::for_display(DisplayVariant::Uc8159_600x448),
+        )?;
+        assert_eq!(inky.eeprom().color().palette().len(), 7);
+        assert!(inky.busy_wait(WaitPhase::Update).active_low());
+
+        // One row of each color in the palette
+        for (row, color) in inky.eeprom().color().palette().iter().enumerate() {
+            inky.canvas_mut()
+                .draw_color(Rectangle::new((row, 0), (row, 599)), *color);
+        }
+        inky.canvas_mut().set_pixel(1, 0, Color::Orange);
+        let packed = inky.canvas().pack_seven_color();
+        assert_eq!(packed.len(), 300 * 448);
+        assert_eq!((packed[0], packed[1], packed[300]), (0x06, 0x00, 0x11));
+        assert_eq!(
+            (packed[1200], packed[1800], packed[2100]),
+            (0x44, 0x66, 0x11)
+        );
+
+        inky.update()?;
+        inky.transport().assert_golden(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/testdata/impression_600x448_palette.trace"
+        ))
+    }
+}
@@ -8,11 +8,11 @@
 //! - 2:4  - The screen height in pixels
 //! - 4:4  - The screen color capabilities (BW, BWR, BWY, or 7-Color)
 //! - 5:5  - The PCB variant - Only has one valid value, reserved for future products
-//! - 6:6  - The display variant - determines what chip the display uses, and what its color
-//!          configuration is
+//! - 6:6  - The display variant - identifies the display model, which determines what chip
+//!   the display uses and what its color configuration is, see [`DisplayModel::info`]
 //! - 7:30 - The time the EEPROM was written
 
-use crate::inky::Color as InkyColor;
+use crate::{inky::Color as InkyColor, lut::LutPreset};
 use anyhow::{bail, ensure, Context, Error, Result};
 use chrono::NaiveDateTime;
 use log::error;
@@ -66,9 +66,9 @@ impl PascalString {
 }
 
 impl From<PascalString> for Vec<u8> {
-    /// Construct a vector from the data in a pascal string
+    /// Construct a vector from the data in a pascal string, led by its length
     fn from(value: PascalString) -> Self {
-        let mut v = vec![value.capacity];
+        let mut v = vec![value.data.len() as u8];
         v.extend(value.data.iter());
         v
     }
@@ -90,7 +90,7 @@ impl TryFrom<&[u8]> for PascalString {
     }
 }
 
-#[derive(Debug, FromPrimitive, ToPrimitive, Clone)]
+#[derive(Debug, FromPrimitive, ToPrimitive, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 /// The color configuration a display supports, as encoded in the EEPROM
 pub enum Color {
@@ -100,17 +100,37 @@ pub enum Color {
     SevenColor = 5,
 }
 
+impl Color {
+    /// Get the colors the display can show
+    pub fn palette(&self) -> &'static [InkyColor] {
+        match self {
+            Color::Black => &[InkyColor::Black, InkyColor::White],
+            Color::Red => &[InkyColor::Black, InkyColor::White, InkyColor::Red],
+            Color::Yellow => &[InkyColor::Black, InkyColor::White, InkyColor::Yellow],
+            Color::SevenColor => &[
+                InkyColor::Black,
+                InkyColor::White,
+                InkyColor::Green,
+                InkyColor::Blue,
+                InkyColor::Red,
+                InkyColor::Yellow,
+                InkyColor::Orange,
+            ],
+        }
+    }
+}
+
 impl TryFrom<Color> for InkyColor {
     type Error = Error;
 
     /// Convert the EEPROM color representation to the drawing representation for the display
-    /// color
+    /// color. Seven color displays have no single color, see [`Color::palette`] instead
     fn try_from(value: Color) -> Result<Self> {
         Ok(match value {
             Color::Black => InkyColor::Black,
             Color::Red => InkyColor::Red,
             Color::Yellow => InkyColor::Yellow,
-            Color::SevenColor => bail!("Cannot convert EEPROM color to Inky Color"),
+            Color::SevenColor => bail!("Seven color displays have no single display color"),
         })
     }
 }
@@ -158,27 +178,178 @@ impl TryFrom<u8> for PcbVariant {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, FromPrimitive, ToPrimitive, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
+/// A display model, as identified by its display variant code in the EEPROM
+pub enum DisplayModel {
+    RedPHatHighTemp = 1,
+    YellowWHat = 2,
+    BlackWHat = 3,
+    BlackPHat = 4,
+    YellowPHat = 5,
+    RedWHat = 6,
+    RedWHatHighTemp = 7,
+    RedWHatv2 = 8,
+    BlackPHatSsd1608 = 10,
+    RedPHatSsd1608 = 11,
+    YellowPHatSsd1608 = 12,
+    SevenColorUc8159 = 14,
+    SevenColor640x400Uc8159 = 15,
+    SevenColor640x400Uc8159v2 = 16,
+    BlackWHatSsd1683 = 17,
+    RedWHatSsd1683 = 18,
+    YellowWHatSsd1683 = 19,
+    SevenColor800x480Ac073Tc1A = 20,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// What the driver knows about a display model
+pub struct ModelInfo {
+    variant: DisplayVariant,
+    width: u16,
+    height: u16,
+    color: Color,
+    high_temp: bool,
+    lut: Option<LutPreset>,
+}
+
+impl ModelInfo {
+    /// Describe a model
+    const fn new(
+        variant: DisplayVariant,
+        (width, height): (u16, u16),
+        color: Color,
+        high_temp: bool,
+        lut: Option<LutPreset>,
+    ) -> Self {
+        Self {
+            variant,
+            width,
+            height,
+            color,
+            high_temp,
+            lut,
+        }
+    }
+
+    /// Get the controller family of the model
+    pub fn variant(&self) -> DisplayVariant {
+        self.variant
+    }
+
+    /// Get the width in pixels of the panel the model ships with
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    /// Get the height in pixels of the panel the model ships with
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// Get the colors the model can show
+    pub fn color(&self) -> Color {
+        self.color
+    }
+
+    /// Get whether the model is a high temperature variant
+    pub fn is_high_temp(&self) -> bool {
+        self.high_temp
+    }
+
+    /// Get the LUT uploaded on updates by default, or `None` if the controller uses its own
+    /// waveforms
+    pub fn lut(&self) -> Option<LutPreset> {
+        self.lut
+    }
+}
+
+impl DisplayModel {
+    /// Get what the driver knows about the model
+    pub fn info(&self) -> ModelInfo {
+        use Color::{Black, Red, SevenColor, Yellow};
+        use DisplayVariant::*;
+        // Resolutions of the panels each model ships with
+        const PHAT: (u16, u16) = (212, 104);
+        const PHAT_SSD1608: (u16, u16) = (250, 122);
+        const WHAT: (u16, u16) = (400, 300);
+
+        match *self {
+            Self::RedPHatHighTemp => {
+                ModelInfo::new(Phat, PHAT, Red, true, Some(LutPreset::RedHighTemp))
+            }
+            Self::YellowWHat => ModelInfo::new(What, WHAT, Yellow, false, Some(LutPreset::Yellow)),
+            Self::BlackWHat => ModelInfo::new(What, WHAT, Black, false, Some(LutPreset::Black)),
+            Self::BlackPHat => ModelInfo::new(Phat, PHAT, Black, false, Some(LutPreset::Black)),
+            Self::YellowPHat => ModelInfo::new(Phat, PHAT, Yellow, false, Some(LutPreset::Yellow)),
+            Self::RedWHat => ModelInfo::new(What, WHAT, Red, false, Some(LutPreset::Red)),
+            Self::RedWHatHighTemp => {
+                ModelInfo::new(What, WHAT, Red, true, Some(LutPreset::RedHighTemp))
+            }
+            Self::RedWHatv2 => ModelInfo::new(What, WHAT, Red, false, Some(LutPreset::Red)),
+            Self::BlackPHatSsd1608 => ModelInfo::new(PhatSsd1608, PHAT_SSD1608, Black, false, None),
+            Self::RedPHatSsd1608 => ModelInfo::new(PhatSsd1608, PHAT_SSD1608, Red, false, None),
+            Self::YellowPHatSsd1608 => {
+                ModelInfo::new(PhatSsd1608, PHAT_SSD1608, Yellow, false, None)
+            }
+            Self::SevenColorUc8159 => {
+                ModelInfo::new(Uc8159_600x448, (600, 448), SevenColor, false, None)
+            }
+            Self::SevenColor640x400Uc8159 | Self::SevenColor640x400Uc8159v2 => {
+                ModelInfo::new(Uc8159_640x400, (640, 400), SevenColor, false, None)
+            }
+            Self::BlackWHatSsd1683 => ModelInfo::new(WhatSsd1683, WHAT, Black, false, None),
+            Self::RedWHatSsd1683 => ModelInfo::new(WhatSsd1683, WHAT, Red, false, None),
+            Self::YellowWHatSsd1683 => ModelInfo::new(WhatSsd1683, WHAT, Yellow, false, None),
+            Self::SevenColor800x480Ac073Tc1A => {
+                ModelInfo::new(Ac073Tc1A, (800, 480), SevenColor, false, None)
+            }
+        }
+    }
+
+    /// Get the colors the model can show
+    pub fn color(&self) -> Color {
+        self.info().color()
+    }
+
+    /// Get the (width, height) in pixels of the panel the model ships with
+    pub fn resolution(&self) -> (u16, u16) {
+        let info = self.info();
+        (info.width(), info.height())
+    }
+
+    /// Get whether the model is a high temperature variant
+    pub fn is_high_temp(&self) -> bool {
+        self.info().is_high_temp()
+    }
+
+    /// Get the controller family of the model
+    pub fn display_variant(&self) -> DisplayVariant {
+        self.info().variant()
+    }
+}
+
+impl From<DisplayModel> for u8 {
+    /// Convert a display model to its display variant code
+    fn from(value: DisplayModel) -> Self {
+        value as u8
+    }
+}
+
+impl TryFrom<u8> for DisplayModel {
+    type Error = Error;
+
+    /// Convert a display variant code read from EEPROM to a display model
+    fn try_from(value: u8) -> Result<Self> {
+        ConvertFromPrimitive::from_u8(value)
+            .context(format!("Invalid value {} for DisplayModel", value))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The controller family of a display, which decides how it is driven. Several display models
+/// share each family, see [`DisplayModel::display_variant`]
 pub enum DisplayVariant {
-    // RedPHatHighTemp = 1,
-    // YellowWHat = 2,
-    // BlackWHat = 3,
-    // BlackPHat = 4,
-    // YellowPHat = 5,
-    // RedWHat = 6,
-    // RedWHatHighTemp = 7,
-    // RedWHatv2 = 8,
-    // BlackPHatSsd1608 = 10,
-    // RedPHatSsd1608 = 11,
-    // YellowPHatSsd1608 = 12,
-    // SevenColorUc8159 = 14,
-    // SevenColor640x400Uc8159 = 15,
-    // SevenColor640x400Uc8159v2 = 16,
-    // BlackWHatSsd1683 = 17,
-    // RedWHatSsd1683 = 18,
-    // YellowWHatSsd1683 = 19,
-    // SevenColor800x480Ac073Tc1A = 20,
     Phat,
     PhatSsd1608,
     What,
@@ -193,16 +364,9 @@ impl TryFrom<u8> for DisplayVariant {
 
     /// Convert a display variant code read from EEPROM to a variant identifier
     fn try_from(value: u8) -> Result<Self> {
-        Ok(match value {
-            1 | 4 | 5 => Self::Phat,
-            10 | 11 | 12 => Self::PhatSsd1608,
-            2 | 3 | 6 | 7 | 8 => Self::What,
-            14 => Self::Uc8159_600x448,
-            15 | 16 => Self::Uc8159_640x400,
-            17 | 18 | 19 => Self::WhatSsd1683,
-            20 => Self::Ac073Tc1A,
-            _ => bail!("Invalid value {} for DisplayVariant", value),
-        })
+        DisplayModel::try_from(value)
+            .map(|model| model.display_variant())
+            .context(format!("Invalid value {} for DisplayVariant", value))
     }
 }
 
@@ -214,7 +378,7 @@ pub struct EEPROM {
     height: u16,
     color: Color,
     pcb_variant: PcbVariant,
-    display_variant: DisplayVariant,
+    display_model: DisplayModel,
     eeprom_write_time: PascalString,
 }
 
@@ -226,7 +390,7 @@ impl From<EEPROM> for Vec<u8> {
         v.extend_from_slice(&value.height.to_le_bytes());
         v.push(value.color as u8);
         v.push(value.pcb_variant as u8);
-        v.push(value.display_variant as u8);
+        v.push(value.display_model.into());
         let write_time: Vec<u8> = value.eeprom_write_time.into();
         v.extend(write_time);
         v
@@ -242,7 +406,7 @@ impl TryFrom<&[u8]> for EEPROM {
         let height = u16::from_le_bytes(value[2..4].try_into()?);
         let color = Color::try_from(value[4])?;
         let pcb_variant = PcbVariant::try_from(value[5])?;
-        let display_variant = DisplayVariant::try_from(value[6])?;
+        let display_model = DisplayModel::try_from(value[6])?;
         let eeprom_write_time_bytes = value[7..]
             .iter()
             .filter(|v| **v != 255)
@@ -256,7 +420,7 @@ impl TryFrom<&[u8]> for EEPROM {
             height,
             color,
             pcb_variant,
-            display_variant,
+            display_model,
             eeprom_write_time,
         })
     }
@@ -268,6 +432,26 @@ impl EEPROM {
     // Give up by default after 10 attempts to read the EEPROM
     pub const DEFAULT_TRIES: usize = 10;
 
+    /// Describe a display of a model with a given resolution, for panels with no readable
+    /// EEPROM. The description has no write time
+    pub fn new(model: DisplayModel, width: u16, height: u16) -> Self {
+        Self {
+            width,
+            height,
+            color: model.color(),
+            pcb_variant: PcbVariant::V1,
+            display_model: model,
+            eeprom_write_time: PascalString::with_capacity(1),
+        }
+    }
+
+    /// Describe a display of a model with the resolution the model ships with, for panels with
+    /// no readable EEPROM
+    pub fn for_model(model: DisplayModel) -> Self {
+        let (width, height) = model.resolution();
+        Self::new(model, width, height)
+    }
+
     /// Try to initialize EEPROM by reading it from the chip a default number of times
     pub fn try_new() -> Result<Self> {
         Self::try_new_tries(Self::DEFAULT_TRIES)
@@ -311,7 +495,7 @@ impl EEPROM {
 
     /// Get the color value of the display
     pub fn color(&self) -> Color {
-        self.color.clone()
+        self.color
     }
 
     /// Get the PCB variant of the display
@@ -321,7 +505,18 @@ impl EEPROM {
 
     /// Get the display variant of the display
     pub fn display_variant(&self) -> DisplayVariant {
-        self.display_variant.clone()
+        self.display_model.display_variant()
+    }
+
+    /// Get the display model of the display
+    pub fn display_model(&self) -> DisplayModel {
+        self.display_model
+    }
+
+    /// Get whether the panel is a high temperature variant, which takes the high temperature
+    /// waveform
+    pub fn is_high_temp(&self) -> bool {
+        self.display_model.is_high_temp()
     }
 
     /// Get the last write time fo the display as a DateTime (with no TZ info)
@@ -336,13 +531,17 @@ impl EEPROM {
 
 #[cfg(test)]
 mod tests {
-    use crate::eeprom::EEPROM;
+    use crate::{
+        eeprom::{Color, DisplayModel, DisplayVariant, EEPROM},
+        lut::LutPreset,
+    };
     // A buffer retrieved with this code:
     // 144, 1, 44, 1, 1, 12, 3, 21, 50, 48, 50, 48, 45, 49, 48, 45, 48, 49, 32, 49, 53, 58, 53, 49, 58, 52, 51, 46, 51, 255, 255, 255
     // A buffer retrieved with smbus2:
     // 144, 1, 44, 1, 1, 12, 3, 21, 50, 48, 50, 48, 45, 49, 48, 45, 48, 49, 32, 49, 53, 58, 53, 49, 58, 52, 51, 46, 51
 
     #[test]
+    #[ignore = "requires an Inky display attached"]
     /// Tests that EEPROM can be initialized by reading it from the device
     /// no specific device is tested for, because you should be able to run
     /// this test on any device with an Inky e-ink display plugged into it.
@@ -350,4 +549,91 @@ mod tests {
     fn init_eeprom() {
         _ = EEPROM::try_new().expect("Failed to initialize eeprom");
     }
+
+    #[test]
+    /// Tests that the EEPROM contents read from a Black wHat decode correctly
+    fn decode_eeprom() {
+        let buffer: &[u8] = &[
+            144, 1, 44, 1, 1, 12, 3, 21, 50, 48, 50, 48, 45, 49, 48, 45, 48, 49, 32, 49, 53, 58,
+            53, 49, 58, 52, 51, 46, 51, 255, 255, 255,
+        ];
+        let eeprom = EEPROM::try_from(buffer).expect("Failed to decode eeprom");
+        assert_eq!(eeprom.width(), 400);
+        assert_eq!(eeprom.height(), 300);
+        assert!(matches!(eeprom.color(), Color::Black));
+        assert!(matches!(eeprom.display_variant(), DisplayVariant::What));
+        assert!(!eeprom.is_high_temp());
+    }
+
+    #[test]
+    /// Tests that the high temperature red wHat decodes as a wHat with the high temperature flag
+    fn decode_high_temp_eeprom() {
+        let buffer: &[u8] = &[
+            144, 1, 44, 1, 2, 12, 7, 21, 50, 48, 50, 48, 45, 49, 48, 45, 48, 49, 32, 49, 53, 58,
+            53, 49, 58, 52, 51, 46, 51,
+        ];
+        let eeprom = EEPROM::try_from(buffer).expect("Failed to decode eeprom");
+        assert!(matches!(eeprom.display_variant(), DisplayVariant::What));
+        assert!(eeprom.is_high_temp());
+    }
+    #[test]
+    /// Tests that a display described in code matches one read from its EEPROM
+    fn eeprom_for_model() {
+        let buffer: &[u8] = &[
+            144, 1, 44, 1, 1, 12, 3, 21, 50, 48, 50, 48, 45, 49, 48, 45, 48, 49, 32, 49, 53, 58,
+            53, 49, 58, 52, 51, 46, 51,
+        ];
+        let read = EEPROM::try_from(buffer).expect("Failed to decode eeprom");
+        let declared = EEPROM::for_model(DisplayModel::BlackWHat);
+        assert_eq!(
+            (declared.width(), declared.height(), declared.color()),
+            (read.width(), read.height(), read.color())
+        );
+        assert_eq!(declared.display_variant(), read.display_variant());
+        assert!(declared.eeprom_write_time().is_err());
+
+        let eeprom = EEPROM::for_model(DisplayModel::RedWHatSsd1683);
+        assert_eq!((eeprom.width(), eeprom.height()), (400, 300));
+        assert_eq!(eeprom.color(), Color::Red);
+        assert_eq!(eeprom.display_variant(), DisplayVariant::WhatSsd1683);
+        assert!(EEPROM::new(DisplayModel::RedWHatHighTemp, 400, 300).is_high_temp());
+
+        assert_eq!(u8::from(DisplayModel::SevenColor800x480Ac073Tc1A), 20);
+        assert!(DisplayModel::try_from(9).is_err());
+    }
+    #[test]
+    /// Tests that EEPROM contents are written back as they were read
+    fn eeprom_round_trip() {
+        let buffer: &[u8] = &[
+            144, 1, 44, 1, 2, 12, 7, 21, 50, 48, 50, 48, 45, 49, 48, 45, 48, 49, 32, 49, 53, 58,
+            53, 49, 58, 52, 51, 46, 51,
+        ];
+        let eeprom = EEPROM::try_from(buffer).expect("Failed to decode eeprom");
+        assert_eq!(eeprom.display_model(), DisplayModel::RedWHatHighTemp);
+        assert_eq!(Vec::<u8>::from(eeprom), buffer);
+    }
+
+    #[test]
+    /// Tests that every display variant code decodes to a model that encodes back to it
+    fn model_table() {
+        for code in 0..=u8::MAX {
+            let Ok(model) = DisplayModel::try_from(code) else {
+                assert!(DisplayVariant::try_from(code).is_err());
+                continue;
+            };
+            assert_eq!(u8::from(model), code);
+            let info = model.info();
+            assert_eq!(DisplayVariant::try_from(code).ok(), Some(info.variant()));
+            // Only SSD1675 panels are sent a preset LUT
+            assert_eq!(
+                info.lut().is_some(),
+                matches!(info.variant(), DisplayVariant::Phat | DisplayVariant::What)
+            );
+        }
+        assert_eq!(
+            DisplayModel::RedPHatHighTemp.info().lut(),
+            Some(LutPreset::RedHighTemp)
+        );
+        assert_eq!(DisplayModel::BlackWHatSsd1683.resolution(), (400, 300));
+    }
 }
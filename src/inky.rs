@@ -1,37 +1,49 @@
 //! Control and draw to the Inky display
 
 use crate::{
-    eeprom::{DisplayVariant, EEPROM},
-    lut::LUT_BLACK,
+    config::InkyConfig,
+    controller::{self, Controller, TEMPERATURE_LEN},
+    eeprom::{Color as EepromColor, DisplayModel, DisplayVariant, EEPROM},
+    lut::{Lut, TemperatureBand, RATED_TEMPERATURE},
+    transport::{BusyWait, DisplayTransport, RppalTransport, WaitPhase},
 };
 use anyhow::{ensure, Context, Error, Result};
 use derive_builder::Builder;
+use log::warn;
 use num_derive::{FromPrimitive, ToPrimitive};
-use num_traits::{FromPrimitive as ConvertFromPrimitive, ToPrimitive as ConvertToPrimitive};
-use rppal::{
-    gpio::{Gpio, InputPin, OutputPin, Trigger},
-    spi::{Bus, Mode, SlaveSelect as SecondarySelect, Spi},
-};
+use num_traits::FromPrimitive as ConvertFromPrimitive;
 use std::{
     borrow::{Borrow, BorrowMut},
     fmt::Display,
-    thread::sleep,
     time::Duration,
 };
 
 #[derive(Builder, Debug)]
 /// Packet used to write to the SPI bus with a command, data, or both
 pub struct SpiPacket {
-    #[builder(setter(strip_option), default)]
-    command: Option<Command>,
+    #[builder(setter(into, strip_option), default)]
+    command: Option<u8>,
     #[builder(default)]
     data: Vec<u8>,
 }
 
+#[derive(Debug)]
+/// A single step of a command sequence sent to the display
+pub enum Operation {
+    /// Pulse the hardware reset line
+    Reset,
+    /// Send a command, data, or both
+    Send(SpiPacket),
+    /// Pause for a fixed duration
+    Delay(Duration),
+    /// Wait for the controller to release the busy line
+    Wait(BusyWait),
+}
+
 impl SpiPacket {
     /// Retrieve the SPI command
     pub fn command(&self) -> Option<u8> {
-        self.command.clone().and_then(|c| c.try_into().ok())
+        self.command
     }
 
     /// Retrieve the SPI data
@@ -64,6 +76,9 @@ pub enum Command {
     SourceDrivingVoltage = 0x4,
     TriggerDisplayUpdate = 0x20,
     VComRegister = 0x2c,
+    TemperatureSensorControl = 0x18,
+    WriteTemperatureRegister = 0x1a,
+    ReadTemperatureRegister = 0x1b,
     SetBWBuffer = 0x24,
     SetRYBuffer = 0x26,
 }
@@ -77,22 +92,26 @@ impl TryFrom<u8> for Command {
     }
 }
 
-impl TryFrom<Command> for u8 {
-    type Error = Error;
-
+impl From<Command> for u8 {
     /// Convert a command to a primitive u8 value
-    fn try_from(value: Command) -> Result<Self> {
-        value.to_u8().context("Not a valid u8 value")
+    fn from(value: Command) -> Self {
+        value as u8
     }
 }
 
-#[derive(Clone, Debug, Copy)]
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
 /// Drawing colors, used on the `Canvas` to draw to the Inky screen
 pub enum Color {
     Red,
     Yellow,
     Black,
     White,
+    Green,
+    Blue,
+    Orange,
+    /// Gray levels, shown on black and white panels in grayscale mode
+    DarkGray,
+    LightGray,
 }
 
 impl Display for Color {
@@ -105,21 +124,76 @@ impl Display for Color {
                 Self::Yellow => "Y",
                 Self::Black => "B",
                 Self::White => ".",
+                Self::Green => "G",
+                Self::Blue => "U",
+                Self::Orange => "O",
+                Self::DarkGray => "#",
+                Self::LightGray => ":",
             }
         )
     }
 }
 
 impl Color {
-    /// Convert the color to u8 for packing
-    // TODO: Support additional displays
+    /// Get the approximate RGB value of the color as it appears on the panel
+    pub fn rgb(&self) -> [u8; 3] {
+        match *self {
+            Self::Red => [0xc8, 0x1e, 0x1e],
+            Self::Yellow => [0xe6, 0xc8, 0x14],
+            Self::Black => [0x00, 0x00, 0x00],
+            Self::White => [0xff, 0xff, 0xff],
+            // Saturated palette of the seven color panels, from the inky library
+            Self::Green => [0x3a, 0x5b, 0x46],
+            Self::Blue => [0x3d, 0x3b, 0x5e],
+            Self::Orange => [0xb1, 0x6a, 0x49],
+            Self::DarkGray => [0x55, 0x55, 0x55],
+            Self::LightGray => [0xaa, 0xaa, 0xaa],
+        }
+    }
+
+    /// Convert the color to its bit in the black/white RAM plane, which is 0 for black. Outside
+    /// grayscale mode, dark gray is shown as black and light gray as white. In grayscale mode,
+    /// the bit and the one in the red/yellow plane select the LUT row driving the pixel: black
+    /// is LUT0, white LUT1, dark gray LUT2 and light gray LUT3
     fn as_u8(&self) -> u8 {
-        if !matches!(*self, Color::Black) {
+        if !matches!(*self, Color::Black | Color::DarkGray) {
+            1
+        } else {
+            0
+        }
+    }
+
+    /// Convert the color to its bit in the red/yellow RAM plane in grayscale mode, which is 1
+    /// for the gray levels
+    fn as_gray_color_u8(&self) -> u8 {
+        if matches!(*self, Color::DarkGray | Color::LightGray) {
+            1
+        } else {
+            0
+        }
+    }
+
+    /// Convert the color to its bit in the red/yellow RAM plane, which is 1 for red or yellow
+    fn as_color_u8(&self) -> u8 {
+        if matches!(*self, Color::Red | Color::Yellow) {
             1
         } else {
             0
         }
     }
+
+    /// Convert the color to its 4 bit index in the palette of seven color panels
+    pub(crate) fn as_seven_color_u8(&self) -> u8 {
+        match *self {
+            Color::Black | Color::DarkGray => 0,
+            Color::White | Color::LightGray => 1,
+            Color::Green => 2,
+            Color::Blue => 3,
+            Color::Red => 4,
+            Color::Yellow => 5,
+            Color::Orange => 6,
+        }
+    }
 }
 
 impl From<u8> for Color {
@@ -210,6 +284,12 @@ impl Rectangle {
         }
     }
 
+    /// Get whether the rectangle contains the pixel at (col, row)
+    fn contains(&self, col: usize, row: usize) -> bool {
+        (self.top_left.0..=self.bottom_right.0).contains(&row)
+            && (self.top_left.1..=self.bottom_right.1).contains(&col)
+    }
+
     // Returns a vector of coordinates inside the rectangle
     fn rectangle_coordinates(&self) -> Vec<(usize, usize)> {
         let mut result = Vec::new();
@@ -230,35 +310,250 @@ impl Drawable for Rectangle {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Placement of the panel pixels in the display RAM. Some panels are mounted rotated relative
+/// to their RAM, or have RAM rows and columns that are not visible.
+pub struct Layout {
+    width: usize,
+    height: usize,
+    ram_width: usize,
+    ram_height: usize,
+    rotated: bool,
+    offset: (usize, usize),
+}
+
+impl Layout {
+    /// Layout of a panel stored in RAM as is, with each row padded to a whole byte
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            ram_width: width.div_ceil(8) * 8,
+            ram_height: height,
+            rotated: false,
+            offset: (0, 0),
+        }
+    }
+
+    /// Layout of a panel rotated by 90 degrees clockwise into a RAM of `ram_width` by
+    /// `ram_height` pixels, with the panel placed at `offset` (col, row) of the unrotated RAM
+    pub fn rotated(
+        width: usize,
+        height: usize,
+        ram_width: usize,
+        ram_height: usize,
+        offset: (usize, usize),
+    ) -> Self {
+        Self {
+            width,
+            height,
+            ram_width,
+            ram_height,
+            rotated: true,
+            offset,
+        }
+    }
+
+    /// Get the width of the panel
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Get the height of the panel
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Get the width of the display RAM in pixels
+    pub fn ram_width(&self) -> usize {
+        self.ram_width
+    }
+
+    /// Get the height of the display RAM in pixels
+    pub fn ram_height(&self) -> usize {
+        self.ram_height
+    }
+
+    /// Get the panel pixel (col, row) stored at a position in the display RAM, if any
+    pub fn ram_to_panel(&self, ram_col: usize, ram_row: usize) -> Option<(usize, usize)> {
+        let (col, row) = if self.rotated {
+            (ram_row, (self.ram_width - 1).checked_sub(ram_col)?)
+        } else {
+            (ram_col, ram_row)
+        };
+        let (col, row) = (
+            col.checked_sub(self.offset.0)?,
+            row.checked_sub(self.offset.1)?,
+        );
+        (col < self.width && row < self.height).then_some((col, row))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// A window of the display RAM, from its first to its last column and row. The columns span
+/// whole bytes of a one bit per pixel RAM plane.
+pub struct RamWindow {
+    first_col: usize,
+    last_col: usize,
+    first_row: usize,
+    last_row: usize,
+}
+
+impl RamWindow {
+    /// Window covering all of the display RAM of a layout
+    pub fn full(layout: &Layout) -> Self {
+        Self {
+            first_col: 0,
+            last_col: layout.ram_width - 1,
+            first_row: 0,
+            last_row: layout.ram_height - 1,
+        }
+    }
+
+    /// Get the first RAM column of the window
+    pub fn first_col(&self) -> usize {
+        self.first_col
+    }
+
+    /// Get the last RAM column of the window
+    pub fn last_col(&self) -> usize {
+        self.last_col
+    }
+
+    /// Get the first RAM row of the window
+    pub fn first_row(&self) -> usize {
+        self.first_row
+    }
+
+    /// Get the last RAM row of the window
+    pub fn last_row(&self) -> usize {
+        self.last_row
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// Clockwise rotation of the canvas on the panel
+pub enum Rotation {
+    #[default]
+    Rotate0,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+impl Rotation {
+    /// Get whether the rotation swaps the width and height of the canvas
+    pub fn is_transposed(&self) -> bool {
+        matches!(*self, Self::Rotate90 | Self::Rotate270)
+    }
+}
+
 pub struct Canvas {
     width: usize,
     height: usize,
     pixels: Vec<Vec<Color>>,
+    layout: Layout,
+    rotation: Rotation,
+    h_flip: bool,
+    v_flip: bool,
 }
 
 impl Canvas {
     /// Create a new drawing canvas with a width and height
-    fn new(width: usize, height: usize) -> Canvas {
+    pub fn new(width: usize, height: usize) -> Canvas {
+        Self::with_layout(Layout::new(width, height))
+    }
+
+    /// Create a new drawing canvas placed in the display RAM as given by a layout
+    pub fn with_layout(layout: Layout) -> Canvas {
         Canvas {
-            width,
-            height,
-            pixels: vec![vec![Color::White; height]; width],
+            width: layout.width,
+            height: layout.height,
+            pixels: vec![vec![Color::White; layout.height]; layout.width],
+            layout,
+            rotation: Rotation::default(),
+            h_flip: false,
+            v_flip: false,
         }
     }
 
+    /// Set the rotation of the canvas on the panel. Rotating by 90 or 270 degrees swaps the
+    /// width and height of the canvas, which clears it
+    pub fn set_rotation(&mut self, rotation: Rotation) {
+        if rotation.is_transposed() != self.rotation.is_transposed() {
+            (self.width, self.height) = (self.height, self.width);
+            self.pixels = vec![vec![Color::White; self.height]; self.width];
+        }
+        self.rotation = rotation;
+    }
+
+    /// Get the rotation of the canvas on the panel
+    pub fn rotation(&self) -> Rotation {
+        self.rotation
+    }
+
+    /// Set whether the canvas is mirrored left to right on the panel
+    pub fn set_h_flip(&mut self, h_flip: bool) {
+        self.h_flip = h_flip;
+    }
+
+    /// Get whether the canvas is mirrored left to right on the panel
+    pub fn h_flip(&self) -> bool {
+        self.h_flip
+    }
+
+    /// Set whether the canvas is mirrored top to bottom on the panel
+    pub fn set_v_flip(&mut self, v_flip: bool) {
+        self.v_flip = v_flip;
+    }
+
+    /// Get whether the canvas is mirrored top to bottom on the panel
+    pub fn v_flip(&self) -> bool {
+        self.v_flip
+    }
+
+    /// Get the canvas pixel (col, row) shown at a pixel of the panel. The canvas is flipped,
+    /// then rotated onto the panel
+    fn panel_to_canvas(&self, col: usize, row: usize) -> (usize, usize) {
+        let (panel_width, panel_height) = (self.layout.width, self.layout.height);
+        let (col, row) = match self.rotation {
+            Rotation::Rotate0 => (col, row),
+            Rotation::Rotate90 => (row, panel_width - 1 - col),
+            Rotation::Rotate180 => (panel_width - 1 - col, panel_height - 1 - row),
+            Rotation::Rotate270 => (panel_height - 1 - row, col),
+        };
+        (
+            if self.h_flip {
+                self.width - 1 - col
+            } else {
+                col
+            },
+            if self.v_flip {
+                self.height - 1 - row
+            } else {
+                row
+            },
+        )
+    }
+
     /// Get the color of a given pixel
-    fn get_pixel(&self, col: usize, row: usize) -> Color {
-        self.pixels[col][row].clone()
+    pub fn get_pixel(&self, col: usize, row: usize) -> Color {
+        self.pixels[col][row]
     }
 
     /// Set the color of a given pixel
-    fn set_pixel(&mut self, col: usize, row: usize, color: Color) {
+    pub fn set_pixel(&mut self, col: usize, row: usize, color: Color) {
         self.pixels[col][row] = color;
     }
 
     pub fn draw<D: Drawable>(&mut self, drawable: D) {
+        self.draw_color(drawable, Color::Black);
+    }
+
+    /// Draw a shape in a given color
+    pub fn draw_color<D: Drawable>(&mut self, drawable: D, color: Color) {
         for (row, col) in drawable.coordinates() {
-            self.set_pixel(col, row, Color::Black);
+            self.set_pixel(col, row, color);
         }
     }
 
@@ -272,14 +567,89 @@ impl Canvas {
         self.width
     }
 
-    /// Bitpack the canvas into bits representing (color|no color) from colored byte pixels
+    /// Bitpack the canvas into the black/white RAM plane, where a 0 bit is black
     pub fn pack(&self) -> Vec<u8> {
+        self.pack_window(&RamWindow::full(&self.layout))
+    }
+
+    /// Bitpack the canvas into the red/yellow RAM plane, where a 1 bit is the panel's color
+    pub fn pack_color(&self) -> Vec<u8> {
+        self.pack_color_window(&RamWindow::full(&self.layout))
+    }
+
+    /// Bitpack a window of the black/white RAM plane
+    pub fn pack_window(&self, window: &RamWindow) -> Vec<u8> {
+        self.pack_bits(window, Color::as_u8)
+    }
+
+    /// Bitpack a window of the red/yellow RAM plane
+    pub fn pack_color_window(&self, window: &RamWindow) -> Vec<u8> {
+        self.pack_bits(window, Color::as_color_u8)
+    }
+
+    /// Bitpack the canvas into the red/yellow RAM plane in grayscale mode
+    pub fn pack_gray_color(&self) -> Vec<u8> {
+        self.pack_gray_color_window(&RamWindow::full(&self.layout))
+    }
+
+    /// Bitpack a window of the red/yellow RAM plane in grayscale mode
+    pub fn pack_gray_color_window(&self, window: &RamWindow) -> Vec<u8> {
+        self.pack_bits(window, Color::as_gray_color_u8)
+    }
+
+    /// Get the smallest window of the display RAM holding every pixel of a region of the
+    /// canvas, or `None` if the region is outside the canvas
+    pub fn ram_window(&self, region: &Rectangle) -> Option<RamWindow> {
+        let mut window: Option<RamWindow> = None;
+        for ram_row in 0..self.layout.ram_height {
+            for ram_col in 0..self.layout.ram_width {
+                let Some((col, row)) = self.layout.ram_to_panel(ram_col, ram_row) else {
+                    continue;
+                };
+                let (col, row) = self.panel_to_canvas(col, row);
+                if !region.contains(col, row) {
+                    continue;
+                }
+                let window = window.get_or_insert(RamWindow {
+                    first_col: ram_col,
+                    last_col: ram_col,
+                    first_row: ram_row,
+                    last_row: ram_row,
+                });
+                window.first_col = window.first_col.min(ram_col);
+                window.last_col = window.last_col.max(ram_col);
+                window.last_row = ram_row;
+            }
+        }
+        // Widen the columns to whole bytes
+        window.map(|window| RamWindow {
+            first_col: window.first_col / 8 * 8,
+            last_col: (window.last_col / 8 * 8 + 7).min(self.layout.ram_width - 1),
+            ..window
+        })
+    }
+
+    /// Get the placement of the canvas in the display RAM
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    /// Pack the canvas into 4 bit palette indices for seven color panels, two pixels per byte
+    /// with the first in the high nibble
+    pub fn pack_seven_color(&self) -> Vec<u8> {
+        self.pack_pixels(&RamWindow::full(&self.layout), 4, Color::as_seven_color_u8)
+    }
+
+    /// Bitpack a window of the canvas into bits representing (color|no color) from colored
+    /// byte pixels. Pixels are packed column by column, least significant bit first, as one
+    /// stream padded to a whole byte at the end
+    fn pack_bits(&self, window: &RamWindow, bit: fn(&Color) -> u8) -> Vec<u8> {
         let mut packed: Vec<u8> = Vec::new();
         let mut bit_pos: u8 = 0;
         let mut cur_byte: u8 = 0;
-        for row in &self.pixels {
-            for b in row {
-                cur_byte |= (b.as_u8()) << bit_pos;
+        for ram_col in window.first_col..=window.last_col {
+            for ram_row in window.first_row..=window.last_row {
+                cur_byte |= bit(&self.ram_color(ram_col, ram_row)) << bit_pos;
                 bit_pos += 1;
                 if bit_pos == 8 {
                     packed.push(cur_byte);
@@ -293,296 +663,486 @@ impl Canvas {
         }
         packed
     }
+
+    /// Pack a window of the canvas into `bits` wide values per pixel, row by row with the
+    /// first pixel in the most significant bits and each row padded to a whole byte
+    fn pack_pixels(&self, window: &RamWindow, bits: u8, value: fn(&Color) -> u8) -> Vec<u8> {
+        let mut packed: Vec<u8> = Vec::new();
+        for ram_row in window.first_row..=window.last_row {
+            let mut bit_pos: u8 = 0;
+            let mut cur_byte: u8 = 0;
+            for ram_col in window.first_col..=window.last_col {
+                let color = self.ram_color(ram_col, ram_row);
+                bit_pos += bits;
+                cur_byte |= value(&color) << (8 - bit_pos);
+                if bit_pos == 8 {
+                    packed.push(cur_byte);
+                    cur_byte = 0;
+                    bit_pos = 0;
+                }
+            }
+            if bit_pos != 0 {
+                packed.push(cur_byte);
+            }
+        }
+        packed
+    }
+
+    /// Get the color of the canvas pixel stored at a position in the display RAM, or white
+    /// for RAM outside the canvas
+    fn ram_color(&self, ram_col: usize, ram_row: usize) -> Color {
+        self.layout
+            .ram_to_panel(ram_col, ram_row)
+            .map_or(Color::White, |(col, row)| {
+                let (col, row) = self.panel_to_canvas(col, row);
+                self.pixels[col][row]
+            })
+    }
 }
 
+/// Function run to put a display to sleep when it is dropped
+type SleepFn<T> = fn(&mut Inky<T>) -> Result<()>;
+
 #[derive(Builder)]
-#[builder(pattern = "owned")]
+#[builder(pattern = "owned", build_fn(private, name = "build_awake"))]
 /// The main display structure, used to control the Inky screen
-pub struct Inky {
-    color: Color,
-    h_flip: bool,
-    v_flip: bool,
-    spi: Spi,
-    // i2c is only used to read EEPROM
-    // i2c: I2c,
-    dc: OutputPin,
-    reset: OutputPin,
-    busy: InputPin,
+pub struct Inky<T = RppalTransport> {
+    color: EepromColor,
+    transport: T,
     eeprom: EEPROM,
     canvas: Canvas,
+    #[builder(default)]
+    busy_timeout: Option<Duration>,
+    #[builder(default = "Color::White")]
+    border_color: Color,
+    #[builder(default)]
+    lut: Option<Lut>,
+    #[builder(default)]
+    temperature: Option<f32>,
+    #[builder(default)]
+    grayscale: bool,
+    #[builder(default)]
+    stay_awake: bool,
+    #[builder(default)]
+    asleep: bool,
+    #[builder(setter(skip))]
+    sleep_on_drop: Option<SleepFn<T>>,
+}
+
+impl<T> Drop for Inky<T> {
+    /// Put the controller into deep sleep, if the display was created with a blocking
+    /// transport and it is not asleep already. The transport is dropped afterwards, which
+    /// releases its GPIO pins
+    fn drop(&mut self) {
+        if let Some(sleep) = self.sleep_on_drop.take() {
+            if let Err(e) = sleep(self) {
+                warn!("Failed to put the display to sleep: {}", e);
+            }
+        }
+    }
+}
+
+impl<T: DisplayTransport> InkyBuilder<T> {
+    /// Build the display, which is put to sleep when it is dropped, as with
+    /// `Inky::with_transport`
+    pub fn build(self) -> Result<Inky<T>, InkyBuilderError> {
+        let mut inky = self.build_awake()?;
+        inky.sleep_on_drop = Some(Inky::sleep);
+        Ok(inky)
+    }
 }
 
 impl TryFrom<EEPROM> for Inky {
     type Error = Error;
 
     fn try_from(value: EEPROM) -> Result<Self> {
-        // TODO: Support additional displays
-        ensure!(
-            matches!(value.display_variant(), DisplayVariant::What),
-            "Only the Inky wHat is supported!"
-        );
-        let gpio = Gpio::new()?;
-
-        let dc_pin = 22;
-        let reset_pin = 27;
-        let busy_pin = 17;
+        Self::with_config(value, &InkyConfig::default())
+    }
+}
 
-        let dc = gpio.get(dc_pin)?;
-        let dc = dc.into_output_low();
-        let reset = gpio.get(reset_pin)?;
-        let reset = reset.into_output_high();
-        let busy = gpio.get(busy_pin)?;
-        let busy = busy.into_input();
+impl Inky {
+    /// Create a display for the panel described by an EEPROM, wired as given in a
+    /// configuration
+    pub fn with_config(eeprom: EEPROM, config: &InkyConfig) -> Result<Self> {
+        let transport = if eeprom.display_variant() == DisplayVariant::Ac073Tc1A {
+            RppalTransport::with_config(&config.with_manual_cs()?)?
+        } else {
+            RppalTransport::with_config(config)?
+        };
+        Self::with_transport(eeprom, transport)
+    }
 
-        let mut inky = InkyBuilder::default()
-            .color(value.color().try_into()?)
-            .h_flip(false)
-            .v_flip(false)
-            .spi(Spi::new(
-                Bus::Spi0,
-                SecondarySelect::Ss0,
-                488_000,
-                Mode::Mode0,
-            )?)
-            .dc(dc)
-            .reset(reset)
-            .busy(busy)
-            .canvas(Canvas::new(value.width() as usize, value.height() as usize))
-            .eeprom(value)
-            .build()?;
+    /// Create a display of a declared model, wired as given in a configuration, for panels
+    /// whose EEPROM is missing or unreadable. Use `EEPROM::new` with `with_config` for a
+    /// panel with a different resolution
+    pub fn with_display(model: DisplayModel, config: &InkyConfig) -> Result<Self> {
+        Self::with_config(EEPROM::for_model(model), config)
+    }
+}
 
+impl<T: DisplayTransport> Inky<T> {
+    /// Create a display for the panel described by an EEPROM, driven over a transport
+    pub fn with_transport(eeprom: EEPROM, transport: T) -> Result<Self> {
+        let mut inky = Self::new(eeprom, transport)?;
+        inky.check_cs(inky.transport.holds_cs())?;
+        inky.sleep_on_drop = Some(Self::sleep);
         inky.reset()?;
-
         Ok(inky)
     }
-}
 
-impl Inky {
-    /// Reset the display
+    /// Reset the display, which also wakes it
     pub fn reset(&mut self) -> Result<()> {
-        self.reset.set_low();
-        // Sleep time from inky library
-        sleep(Duration::from_millis(100));
-        self.reset.set_high();
-        sleep(Duration::from_millis(100));
-        self.spi_send(
-            SpiPacketBuilder::default()
-                .command(Command::SoftReset)
-                .build()?,
-        )?;
-        self.wait()?;
+        let ops = self.reset_sequence()?;
+        let result = self.run(ops);
+        self.asleep = result.is_err();
+        result
+    }
+
+    /// Update the display to show the contents of the canvas
+    pub fn update(&mut self) -> Result<()> {
+        let ops = self.update_sequence()?;
+        let result = self.run(ops);
+        self.updated(result)
+    }
+
+    /// Update the display to show the contents of a region of the canvas, using a fast
+    /// partial refresh where the controller supports one
+    pub fn update_region(&mut self, region: Rectangle) -> Result<()> {
+        let ops = self.update_region_sequence(&region)?;
+        let result = self.run(ops);
+        self.updated(result)
+    }
+
+    /// Put the controller into deep sleep until the next update or `wake`. Does nothing if it
+    /// is asleep already
+    pub fn sleep(&mut self) -> Result<()> {
+        if self.asleep {
+            return Ok(());
+        }
+        let ops = self.sleep_sequence()?;
+        self.run(ops)?;
+        self.asleep = true;
         Ok(())
     }
 
-    pub fn canvas(&self) -> &Canvas {
-        &self.canvas
+    /// Wake the controller from deep sleep. Does nothing if it is awake already
+    pub fn wake(&mut self) -> Result<()> {
+        if !self.asleep {
+            return Ok(());
+        }
+        self.reset()
     }
 
-    pub fn canvas_mut(&mut self) -> &mut Canvas {
-        &mut self.canvas
+    /// Power off the panel drivers, leaving the controller awake. The controller is woken first
+    /// if it is asleep
+    pub fn power_off(&mut self) -> Result<()> {
+        self.wake()?;
+        let ops = self.power_off_sequence()?;
+        self.run(ops)
     }
 
-    /// Update the display to show the contents of the canvas
-    pub fn update(&mut self) -> Result<()> {
-        self.spi_send(
-            SpiPacketBuilder::default()
-                .command(Command::SetAnalogBlockControl)
-                .data(vec![0x54])
-                .build()?,
-        )?;
+    /// Run a sequence of operations against the display
+    pub fn run(&mut self, operations: Vec<Operation>) -> Result<()> {
+        for operation in operations {
+            match operation {
+                Operation::Reset => self.transport.reset()?,
+                Operation::Send(packet) => self.spi_send(packet)?,
+                Operation::Delay(duration) => self.transport.delay(duration),
+                Operation::Wait(wait) => self.transport.wait(&wait)?,
+            }
+        }
+        Ok(())
+    }
 
-        self.spi_send(
-            SpiPacketBuilder::default()
-                .command(Command::SetDigitalBlockControl)
-                .data(vec![0x3b])
-                .build()?,
-        )?;
+    /// Measure the panel temperature with the controller's sensor and use it to select the
+    /// waveform LUT. Fails if the controller or transport cannot read the temperature
+    pub fn read_temperature(&mut self) -> Result<f32> {
+        ensure!(
+            self.transport.can_read(),
+            "The transport cannot read the temperature from the controller"
+        );
+        let mut ops = self.reset_sequence()?;
+        ops.extend(self.controller().measure_temperature(self)?);
+        let result = self.run(ops);
+        self.asleep = result.is_err();
+        result?;
+        let data = self.transport.read_data(TEMPERATURE_LEN)?;
+        let celsius = self.controller().decode_temperature(&data)?;
+        self.set_temperature(Some(celsius));
+        Ok(celsius)
+    }
 
-        let mut gate_setting_data = (self.canvas.height() as u16).to_le_bytes().to_vec();
-        gate_setting_data.push(0x00);
+    /// Wait for the display to release the busy line during a phase
+    pub fn wait(&mut self, phase: WaitPhase) -> Result<()> {
+        let wait = self.busy_wait(phase);
+        self.transport.wait(&wait)
+    }
 
-        self.spi_send(
-            SpiPacketBuilder::default()
-                .command(Command::GateSetting)
-                .data(gate_setting_data)
-                .build()?,
-        )?;
-        self.spi_send(
-            SpiPacketBuilder::default()
-                .command(Command::GateDrivingVoltage)
-                .data(vec![0x17])
-                .build()?,
-        )?;
+    /// Send a packet over the SPI bus
+    pub fn spi_send(&mut self, packet: SpiPacket) -> Result<()> {
+        self.transport.send_packet(packet.command(), &packet.data)
+    }
+}
 
-        self.spi_send(
-            SpiPacketBuilder::default()
-                .command(Command::SourceDrivingVoltage)
-                .data(vec![0x41, 0xAC, 0x32])
-                .build()?,
-        )?;
-        self.spi_send(
-            SpiPacketBuilder::default()
-                .command(Command::DummyLinePeriod)
-                .data(vec![0x07])
-                .build()?,
-        )?;
-        self.spi_send(
-            SpiPacketBuilder::default()
-                .command(Command::GateLineWidth)
-                .data(vec![0x04])
-                .build()?,
-        )?;
-        self.spi_send(
-            SpiPacketBuilder::default()
-                .command(Command::DataEntryMode)
-                .data(vec![0x03])
-                .build()?,
-        )?;
-        self.spi_send(
-            SpiPacketBuilder::default()
-                .command(Command::VComRegister)
-                .data(vec![0x3c])
-                .build()?,
-        )?;
+impl<T> Inky<T> {
+    // How long to wait for the controller to come out of reset, go to sleep, or power the
+    // panel on or off
+    pub const DEFAULT_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Create a display for the panel described by an EEPROM without resetting it
+    pub(crate) fn new(eeprom: EEPROM, transport: T) -> Result<Self> {
+        Ok(InkyBuilder::default()
+            .color(eeprom.color())
+            .transport(transport)
+            .canvas(Canvas::with_layout(Self::layout(&eeprom)))
+            .eeprom(eeprom)
+            .build_awake()?)
+    }
 
-        // TODO: Make this depend on color:
-        // if self.border_colour == self.BLACK:
-        //     self._send_command(0x3c, 0b00000000)  # GS Transition Define A + VSS + LUT0
-        // elif self.border_colour == self.RED and self.colour == 'red':
-        //     self._send_command(0x3c, 0b01110011)  # Fix Level Define A + VSH2 + LUT3
-        // elif self.border_colour == self.YELLOW and self.colour == 'yellow':
-        //     self._send_command(0x3c, 0b00110011)  # GS Transition Define A + VSH2 + LUT3
-        // elif self.border_colour == self.WHITE:
-        //     self._send_command(0x3c, 0b00110001)  # GS Transition Define A + VSH2 + LUT1
-        self.spi_send(
-            SpiPacketBuilder::default()
-                .command(Command::GSTransition)
-                .data(vec![0b00110001])
-                .build()?,
-        )?;
+    /// Fail if the controller needs chip select held for a whole command and its data, and the
+    /// transport cannot hold it
+    pub(crate) fn check_cs(&self, holds_cs: bool) -> Result<()> {
+        ensure!(
+            holds_cs || !self.controller().needs_held_cs(),
+            "The {:?} display needs chip select held for a whole command and its data, which \
+             the transport cannot do",
+            self.eeprom.display_variant()
+        );
+        Ok(())
+    }
 
-        self.spi_send(
-            SpiPacketBuilder::default()
-                .command(Command::SetLUT)
-                .data(LUT_BLACK.to_vec())
-                .build()?,
-        )?;
+    /// Get the placement of the canvas in the display RAM for the panel described by an
+    /// EEPROM
+    pub(crate) fn layout(eeprom: &EEPROM) -> Layout {
+        controller::for_variant::<T>(eeprom.display_variant()).layout(eeprom)
+    }
 
-        self.spi_send(
-            SpiPacketBuilder::default()
-                .command(Command::SetRamXStartEnd)
-                .data(vec![0x00, ((self.canvas.width() / 8) - 1) as u8])
-                .build()?,
-        )?;
+    /// Get the controller of the display
+    pub fn controller(&self) -> &dyn Controller<T> {
+        controller::for_variant(self.eeprom.display_variant())
+    }
 
-        let mut data = vec![0x00, 0x00];
-        data.extend_from_slice(&(self.canvas.height() as u16).to_le_bytes());
+    /// Build the sequence of operations that resets the display
+    pub fn reset_sequence(&self) -> Result<Vec<Operation>> {
+        self.controller().reset(self)
+    }
 
-        self.spi_send(
-            SpiPacketBuilder::default()
-                .command(Command::SetRamYStartEnd)
-                .data(data)
-                .build()?,
-        )?;
+    /// Build the sequence of operations that puts the controller into deep sleep
+    pub fn sleep_sequence(&self) -> Result<Vec<Operation>> {
+        self.controller().sleep(self)
+    }
 
-        let bw_buf = self.canvas.pack();
-        // 0 because nothing == RED
-        // let ry_buf = vec![0; bw_buf.len()];
+    /// Build the sequence of operations that wakes the controller from deep sleep
+    pub fn wake_sequence(&self) -> Result<Vec<Operation>> {
+        self.controller().wake(self)
+    }
 
-        self.spi_send(
-            SpiPacketBuilder::default()
-                .command(Command::SetRamXPointerStart)
-                .data(vec![0x00])
-                .build()?,
-        )?;
+    /// Build the sequence of operations that powers off the panel drivers
+    pub fn power_off_sequence(&self) -> Result<Vec<Operation>> {
+        self.controller().power_off(self)
+    }
 
-        self.spi_send(
-            SpiPacketBuilder::default()
-                .command(Command::SetRamYPointerStart)
-                .data(vec![0x00, 0x00])
-                .build()?,
-        )?;
+    /// Set whether the controller stays awake after updates instead of entering deep sleep,
+    /// which saves resetting it between rapid updates. Call `sleep` when done updating
+    pub fn set_stay_awake(&mut self, stay_awake: bool) {
+        self.stay_awake = stay_awake;
+    }
 
-        self.spi_send(
-            SpiPacketBuilder::default()
-                .command(Command::SetBWBuffer)
-                .data(bw_buf)
-                .build()?,
-        )?;
+    /// Get whether the controller stays awake after updates
+    pub fn stay_awake(&self) -> bool {
+        self.stay_awake
+    }
 
-        // TODO: Support additional displays
-        // self.spi_send(
-        //     SpiPacketBuilder::default()
-        //         .command(Command::SetRamXPointerStart)
-        //         .data(vec![0x00])
-        //         .build()?,
-        // )?;
-
-        // self.spi_send(
-        //     SpiPacketBuilder::default()
-        //         .command(Command::SetRamYPointerStart)
-        //         .data(vec![0x00, 0x00])
-        //         .build()?,
-        // )?;
-
-        // self.spi_send(
-        //     SpiPacketBuilder::default()
-        //         .command(Command::SetRYBuffer)
-        //         .data(ry_buf)
-        //         .build()?,
-        // )?;
-
-        self.spi_send(
-            SpiPacketBuilder::default()
-                .command(Command::DisplayUpdateSequence)
-                .data(vec![0xc7])
-                .build()?,
-        )?;
+    /// Get whether the controller is in deep sleep, or in an unknown state after a failure,
+    /// and is reset before the next update
+    pub fn is_asleep(&self) -> bool {
+        self.asleep
+    }
 
-        self.spi_send(
-            SpiPacketBuilder::default()
-                .command(Command::TriggerDisplayUpdate)
-                .build()?,
-        )?;
+    /// Record the state the controller is left in by running an update sequence
+    pub(crate) fn updated(&mut self, result: Result<()>) -> Result<()> {
+        self.asleep = result.is_err() || !self.stay_awake;
+        result
+    }
 
-        // Defined by inky
-        sleep(Duration::from_secs_f32(0.05));
+    /// Record the state the controller is left in by running a reset or sleep sequence
+    pub(crate) fn set_asleep(&mut self, asleep: bool) {
+        self.asleep = asleep;
+    }
 
-        self.wait()?;
+    /// Wake the controller before, and put it to sleep after, the operations of an update
+    fn around_update(&self, update: Vec<Operation>) -> Result<Vec<Operation>> {
+        let mut ops = if self.asleep {
+            self.wake_sequence()?
+        } else {
+            Vec::new()
+        };
+        ops.extend(update);
+        if !self.stay_awake {
+            ops.extend(self.sleep_sequence()?);
+        }
+        Ok(ops)
+    }
 
-        self.spi_send(
-            SpiPacketBuilder::default()
-                .command(Command::EnterDeepSleep)
-                .data(vec![0x01])
-                .build()?,
-        )?;
+    /// Estimate how long a refresh of the display takes. Controllers that are sent a LUT
+    /// estimate it from the LUT timings, and others use the time of their built-in waveform
+    pub fn estimated_refresh_time(&self) -> Duration {
+        self.controller().refresh_time(self)
+    }
+
+    /// Set how long to wait for the busy line before giving up. With `None`, waits for an
+    /// update are allowed twice the refresh time estimated by the controller, and other waits
+    /// are allowed `DEFAULT_BUSY_TIMEOUT`
+    pub fn set_busy_timeout(&mut self, timeout: Option<Duration>) {
+        self.busy_timeout = timeout;
+    }
+
+    /// Get how long to wait for the busy line during a phase before giving up
+    pub fn busy_timeout(&self, phase: WaitPhase) -> Duration {
+        self.busy_timeout.unwrap_or(match phase {
+            WaitPhase::Update => {
+                (self.estimated_refresh_time() * 2).max(Self::DEFAULT_BUSY_TIMEOUT)
+            }
+            WaitPhase::Reset
+            | WaitPhase::DeepSleep
+            | WaitPhase::PowerOn
+            | WaitPhase::PowerOff
+            | WaitPhase::Temperature => Self::DEFAULT_BUSY_TIMEOUT,
+        })
+    }
 
+    /// Set the color of the border around the panel, which is shown from the next update.
+    /// Fails if the panel cannot show the color
+    pub fn set_border_color(&mut self, color: Color) -> Result<()> {
+        ensure!(
+            self.color.palette().contains(&color),
+            "{:?} panels cannot show a {:?} border",
+            self.color,
+            color
+        );
+        self.border_color = color;
         Ok(())
     }
 
-    /// Wait for the display to update
-    pub fn wait(&mut self) -> Result<()> {
-        self.busy.set_interrupt(Trigger::FallingEdge)?;
-        self.busy.poll_interrupt(false, None)?;
-        self.busy.clear_interrupt()?;
+    /// Get the color of the border around the panel
+    pub fn border_color(&self) -> Color {
+        self.border_color
+    }
+
+    /// Set the waveform LUT used by updates, such as a preset from `Lut::preset` or one
+    /// loaded with `Lut::from_file`. With `None`, the LUT for the panel color is used. Fails
+    /// if the controller uses its built-in waveforms
+    pub fn set_lut(&mut self, lut: Option<Lut>) -> Result<()> {
+        ensure!(
+            lut.is_none() || self.controller().supports_custom_lut(),
+            "The {:?} display does not take a custom LUT",
+            self.eeprom.display_variant()
+        );
+        self.lut = lut;
         Ok(())
     }
 
-    /// Send a packet over the SPI bus
-    pub fn spi_send(&mut self, packet: SpiPacket) -> Result<()> {
-        if let Some(command) = packet.command() {
-            self.dc.set_low();
-            self.spi.write(&[command])?;
-        }
+    /// Get the waveform LUT set for updates, if any
+    pub fn lut(&self) -> Option<&Lut> {
+        self.lut.as_ref()
+    }
 
-        if !packet.data().is_empty() {
-            self.dc.set_high();
-            for chunk in packet.data().chunks(4096) {
-                self.spi.write(chunk)?;
-            }
+    /// Set whether updates show the gray levels of the canvas, using both RAM planes and a
+    /// grayscale LUT. Fails if the display cannot show gray levels
+    pub fn set_grayscale(&mut self, grayscale: bool) -> Result<()> {
+        ensure!(
+            !grayscale || self.controller().supports_grayscale(self),
+            "The {:?} display cannot show gray levels",
+            self.eeprom.display_variant()
+        );
+        self.grayscale = grayscale;
+        Ok(())
+    }
+
+    /// Get whether updates show the gray levels of the canvas
+    pub fn grayscale(&self) -> bool {
+        self.grayscale
+    }
+
+    /// Set the panel temperature in degrees Celsius, which selects the waveform LUT for its
+    /// temperature band. With `None`, the LUT for normal temperatures is used. Controllers with
+    /// built-in waveforms measure the temperature themselves
+    pub fn set_temperature(&mut self, celsius: Option<f32>) {
+        if let Some(celsius) = celsius.filter(|c| !RATED_TEMPERATURE.contains(c)) {
+            warn!(
+                "Panel temperature {:.1}°C is outside the rated {:.0}°C to {:.0}°C",
+                celsius,
+                RATED_TEMPERATURE.start(),
+                RATED_TEMPERATURE.end()
+            );
         }
+        self.temperature = celsius;
+    }
 
-        Ok(())
+    /// Get the panel temperature in degrees Celsius, if known
+    pub fn temperature(&self) -> Option<f32> {
+        self.temperature
+    }
+
+    /// Get the temperature band of the panel, which is normal if the temperature is unknown
+    pub fn temperature_band(&self) -> TemperatureBand {
+        self.temperature
+            .map_or(TemperatureBand::Normal, TemperatureBand::of)
+    }
+
+    /// Get the busy wait for a phase
+    pub(crate) fn busy_wait(&self, phase: WaitPhase) -> BusyWait {
+        BusyWait::new(phase, self.busy_timeout(phase))
+            .with_active_low(self.controller().busy_active_low())
+    }
+
+    /// Get the color configuration of the display
+    pub fn color(&self) -> EepromColor {
+        self.color
+    }
+
+    /// Get the EEPROM the display was created from
+    pub fn eeprom(&self) -> &EEPROM {
+        &self.eeprom
+    }
+
+    /// Get the transport used to talk to the display
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Get the transport used to talk to the display mutably
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    pub fn canvas(&self) -> &Canvas {
+        &self.canvas
+    }
+
+    pub fn canvas_mut(&mut self) -> &mut Canvas {
+        &mut self.canvas
+    }
+
+    /// Build the sequence of operations that updates the display to show the contents of the
+    /// canvas
+    pub fn update_sequence(&self) -> Result<Vec<Operation>> {
+        self.around_update(self.controller().update(self)?)
+    }
+
+    /// Build the sequence of operations that updates the display to show the contents of a
+    /// region of the canvas. Only the display RAM covering the region is written
+    pub fn update_region_sequence(&self, region: &Rectangle) -> Result<Vec<Operation>> {
+        let window = self
+            .canvas
+            .ram_window(region)
+            .context("Region is outside the canvas")?;
+        let controller = self.controller();
+        self.around_update(controller.update_window(
+            self,
+            &window,
+            controller.supports_partial(self),
+        )?)
     }
 }
 
@@ -615,14 +1175,494 @@ impl BorrowMut<[u32]> for BufferWrapper {
 
 #[cfg(test)]
 mod tests {
-    use std::borrow::{Borrow, BorrowMut};
-
-    use super::{BufferWrapper, Color, Inky, Rectangle};
-    use crate::eeprom::EEPROM;
+    use super::{Canvas, Color, Inky, InkyBuilder, RamWindow, Rectangle, Rotation};
+    use crate::{
+        eeprom::{Color as EepromColor, DisplayModel, DisplayVariant, EEPROM},
+        lut::{Lut, LutPreset, TemperatureBand, LUT_BLACK, LUT_GRAY, LUT_RED, LUT_RED_HIGHTEMP},
+        mock::{MockTransport, TraceEvent},
+        transport::{BusyTimeout, BusyWait, DisplayTransport, WaitPhase},
+    };
     use anyhow::Result;
+    use std::time::Duration;
+
+    fn mock_inky() -> Result<Inky<MockTransport>> {
+        mock_display(DisplayModel::BlackWHat)
+    }
+
+    fn mock_display(model: DisplayModel) -> Result<Inky<MockTransport>> {
+        Inky::with_transport(EEPROM::for_model(model), MockTransport::new())
+    }
+
+    fn golden(name: &str) -> String {
+        format!("{}/testdata/{}.trace", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    #[test]
+    fn test_reset_trace() -> Result<()> {
+        let inky = mock_inky()?;
+        inky.transport().assert_golden(golden("what_black_reset"))
+    }
 
     #[test]
     fn test_blank() -> Result<()> {
+        let mut inky = mock_inky()?;
+        inky.transport_mut().clear();
+        inky.update()?;
+        inky.transport().assert_golden(golden("what_black_blank"))
+    }
+
+    #[test]
+    fn test_draw_box() -> Result<()> {
+        let mut inky = mock_inky()?;
+        inky.transport_mut().clear();
+
+        inky.canvas_mut().draw(Rectangle::new((0, 0), (299, 399)));
+
+        assert!(inky.canvas().pack().iter().all(|b| *b == 0));
+        inky.update()?;
+        inky.transport().assert_golden(golden("what_black_box"))
+    }
+
+    #[test]
+    fn test_red_planes() -> Result<()> {
+        let mut inky = mock_display(DisplayModel::RedWHat)?;
+        inky.transport_mut().clear();
+
+        inky.canvas_mut().draw(Rectangle::new((0, 0), (0, 7)));
+        inky.canvas_mut()
+            .draw_color(Rectangle::new((1, 0), (1, 7)), Color::Red);
+
+        // Red is white in the black/white plane, and set in the color plane. The second column
+        // starts half way through byte 37, and the ninth at byte 300
+        let (bw, ry) = (inky.canvas().pack(), inky.canvas().pack_color());
+        assert_eq!((bw[0], bw[37], bw[300]), (0xfe, 0xef, 0xff));
+        assert_eq!((ry[0], ry[37], ry[300]), (0x02, 0x20, 0x00));
+
+        inky.update()?;
+        inky.transport().assert_golden(golden("what_red_lines"))
+    }
+
+    #[test]
+    fn test_phat_rotation() -> Result<()> {
+        let mut inky = mock_display(DisplayModel::BlackPHat)?;
+        inky.transport_mut().clear();
+        assert_eq!((inky.canvas().width(), inky.canvas().height()), (212, 104));
+
+        // The top left pixel is the first of the last RAM column, and the top right pixel is
+        // the last bit of the plane
+        inky.canvas_mut().set_pixel(0, 0, Color::Black);
+        inky.canvas_mut().set_pixel(211, 0, Color::Black);
+        let bw = inky.canvas().pack();
+        assert_eq!(bw.len(), 13 * 212);
+        assert_eq!((bw[0], bw[103 * 212 / 8]), (0xff, 0xef));
+        assert_eq!(bw[bw.len() - 1], 0x7f);
+
+        inky.canvas_mut().draw(Rectangle::new((0, 0), (103, 211)));
+        inky.update()?;
+        inky.transport().assert_golden(golden("phat_black_box"))
+    }
+
+    #[test]
+    fn test_pack_order() {
+        let mut canvas = Canvas::new(16, 2);
+        canvas.set_pixel(0, 0, Color::Black);
+        canvas.set_pixel(9, 0, Color::Black);
+        canvas.set_pixel(1, 1, Color::Black);
+
+        // Column by column, with the first row of each column in the lowest bit
+        assert_eq!(canvas.pack(), [0xf6, 0xff, 0xfb, 0xff]);
+    }
+
+    #[test]
+    fn test_canvas_orientation() {
+        let mut canvas = Canvas::new(400, 300);
+        canvas.set_rotation(Rotation::Rotate90);
+        assert_eq!((canvas.width(), canvas.height()), (300, 400));
+
+        // Rotated clockwise, the top left of the canvas is the top right of the panel
+        canvas.set_pixel(0, 0, Color::Black);
+        let bw = canvas.pack();
+        assert_eq!((bw[0], bw[399 * 300 / 8]), (0xff, 0xef));
+
+        // Upside down, it is the bottom right
+        canvas.set_rotation(Rotation::Rotate180);
+        assert_eq!((canvas.width(), canvas.height()), (400, 300));
+        canvas.set_pixel(0, 0, Color::Black);
+        assert_eq!(canvas.pack()[bw.len() - 1], 0x7f);
+
+        // Turned back by 270 degrees, the bottom left
+        canvas.set_rotation(Rotation::Rotate270);
+        canvas.set_pixel(0, 0, Color::Black);
+        assert_eq!(canvas.pack()[299 / 8], 0xf7);
+
+        // Mirrored both ways, the bottom right again
+        canvas.set_rotation(Rotation::Rotate0);
+        canvas.set_h_flip(true);
+        canvas.set_v_flip(true);
+        canvas.set_pixel(0, 0, Color::Black);
+        assert_eq!(canvas.pack()[bw.len() - 1], 0x7f);
+    }
+
+    #[test]
+    fn test_border_color() -> Result<()> {
+        assert!(mock_inky()?.set_border_color(Color::Red).is_err());
+
+        let mut inky = mock_display(DisplayModel::RedWHat)?;
+        assert_eq!(inky.border_color(), Color::White);
+        assert!(inky.set_border_color(Color::Yellow).is_err());
+        inky.set_border_color(Color::Red)?;
+        assert_eq!(inky.border_color(), Color::Red);
+
+        inky.transport_mut().clear();
+        inky.update()?;
+        let events = inky.transport().events();
+        let gs_transition = events
+            .iter()
+            .position(|e| *e == TraceEvent::Command(0x3c))
+            .expect("No GS transition command sent");
+        assert_eq!(
+            events[gs_transition + 1],
+            TraceEvent::Data(vec![0b01110011])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_region() -> Result<()> {
+        let mut inky = mock_inky()?;
+        inky.transport_mut().clear();
+
+        // Rows 10 to 19 and columns 20 to 29 are written as whole bytes of columns 16 to 31
+        let region = || Rectangle::new((10, 20), (19, 29));
+        inky.canvas_mut().draw(region());
+        let window = inky.canvas().ram_window(&region()).expect("No window");
+        assert_eq!(
+            window,
+            RamWindow {
+                first_col: 16,
+                last_col: 31,
+                first_row: 10,
+                last_row: 19,
+            }
+        );
+        // Ten rows per column: four white columns, ten black ones, then two white ones
+        let mut packed = vec![0xff; 5];
+        packed.extend([0x00; 12]);
+        packed.extend([0xf0, 0xff, 0xff]);
+        assert_eq!(inky.canvas().pack_window(&window), packed);
+
+        inky.update_region(region())?;
+        inky.transport()
+            .assert_golden(golden("what_black_region"))?;
+
+        assert!(inky
+            .update_region(Rectangle::new((300, 0), (310, 10)))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_phat_ram_window() -> Result<()> {
+        let inky = mock_display(DisplayModel::BlackPHat)?;
+
+        // The top left pixel is at the end of the first RAM row
+        let window = inky.canvas().ram_window(&Rectangle::new((0, 0), (0, 0)));
+        assert_eq!(
+            window,
+            Some(RamWindow {
+                first_col: 96,
+                last_col: 103,
+                first_row: 0,
+                last_row: 0,
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_estimated_refresh_time() -> Result<()> {
+        let inky = mock_inky()?;
+        assert_eq!(
+            inky.estimated_refresh_time(),
+            Lut::preset(LutPreset::Black)?.estimated_refresh_time()
+        );
+        assert_eq!(
+            inky.busy_timeout(WaitPhase::Update),
+            inky.estimated_refresh_time() * 2
+        );
+
+        let impression = Inky::with_transport(
+            EEPROM::for_model(DisplayModel::SevenColorUc8159),
+            MockTransport::for_display(DisplayVariant::Uc8159_600x448),
+        )?;
+        assert_eq!(impression.estimated_refresh_time(), Duration::from_secs(16));
+        Ok(())
+    }
+
+    #[test]
+    fn test_custom_lut() -> Result<()> {
+        let mut inky = mock_inky()?;
+        let timeout = inky.busy_timeout(WaitPhase::Update);
+        let fast = Lut::preset(LutPreset::FastBlack)?;
+        inky.set_lut(Some(fast.clone()))?;
+        assert!(inky.busy_timeout(WaitPhase::Update) < timeout);
+
+        inky.transport_mut().clear();
+        inky.update()?;
+        let events = inky.transport().events();
+        let set_lut = events
+            .iter()
+            .position(|e| *e == TraceEvent::Command(0x32))
+            .expect("No LUT sent");
+        assert_eq!(events[set_lut + 1], TraceEvent::Data(Vec::from(&fast)));
+
+        let mut impression = Inky::with_transport(
+            EEPROM::for_model(DisplayModel::SevenColorUc8159),
+            MockTransport::for_display(DisplayVariant::Uc8159_600x448),
+        )?;
+        assert!(impression.set_lut(Some(fast)).is_err());
+        impression.set_lut(None)
+    }
+
+    /// Mock transport without a data line from the controller
+    struct WriteOnly(MockTransport);
+
+    impl DisplayTransport for WriteOnly {
+        fn send_command(&mut self, command: u8) -> Result<()> {
+            self.0.send_command(command)
+        }
+
+        fn send_data(&mut self, data: &[u8]) -> Result<()> {
+            self.0.send_data(data)
+        }
+
+        fn reset(&mut self) -> Result<()> {
+            self.0.reset()
+        }
+
+        fn wait(&mut self, wait: &BusyWait) -> Result<()> {
+            self.0.wait(wait)
+        }
+    }
+
+    /// Get the LUT sent by the last update recorded by a mock transport
+    fn sent_lut(inky: &Inky<MockTransport>) -> Option<Vec<u8>> {
+        let events = inky.transport().events();
+        let set_lut = events
+            .iter()
+            .position(|e| *e == TraceEvent::Command(0x32))?;
+        match &events[set_lut + 1] {
+            TraceEvent::Data(data) => Some(data.clone()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_sleep_and_wake() -> Result<()> {
+        let sleep = TraceEvent::Command(0x10);
+        let mut mock = MockTransport::new();
+        {
+            let mut inky =
+                Inky::with_transport(EEPROM::for_model(DisplayModel::BlackWHat), &mut mock)?;
+            assert!(!inky.is_asleep());
+            inky.update()?;
+            assert!(inky.is_asleep());
+            assert_eq!(
+                inky.transport().events()[inky.transport().events().len() - 2],
+                sleep
+            );
+
+            // Waking from deep sleep takes a reset
+            inky.transport_mut().clear();
+            inky.update()?;
+            assert_eq!(inky.transport().events()[0], TraceEvent::Reset);
+
+            // Staying awake skips the reset and the deep sleep
+            inky.set_stay_awake(true);
+            inky.update()?;
+            inky.transport_mut().clear();
+            inky.update()?;
+            assert!(!inky.is_asleep());
+            assert_ne!(inky.transport().events()[0], TraceEvent::Reset);
+            assert!(!inky.transport().events().contains(&sleep));
+
+            inky.transport_mut().clear();
+            inky.power_off()?;
+            assert_eq!(
+                inky.transport().events().last(),
+                Some(&TraceEvent::Wait(WaitPhase::PowerOff))
+            );
+            inky.transport_mut().clear();
+        }
+        // Dropping the display puts it to sleep
+        assert_eq!(mock.events(), [sleep.clone(), TraceEvent::Data(vec![0x01])]);
+
+        // So does dropping one made with the builder
+        let mut mock = MockTransport::new();
+        let inky = InkyBuilder::default()
+            .color(EepromColor::Black)
+            .transport(&mut mock)
+            .canvas(Canvas::new(400, 300))
+            .eeprom(EEPROM::for_model(DisplayModel::BlackWHat))
+            .build()?;
+        drop(inky);
+        assert_eq!(mock.events(), [sleep, TraceEvent::Data(vec![0x01])]);
+
+        // A display that is asleep already is left alone
+        let mut mock = MockTransport::new();
+        let mut inky = Inky::with_transport(EEPROM::for_model(DisplayModel::BlackWHat), &mut mock)?;
+        inky.sleep()?;
+        inky.transport_mut().clear();
+        drop(inky);
+        assert!(mock.events().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_grayscale() -> Result<()> {
+        let mut inky = mock_inky()?;
+        for (row, color) in [
+            Color::Black,
+            Color::DarkGray,
+            Color::LightGray,
+            Color::White,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            inky.canvas_mut().set_pixel(0, row, color);
+        }
+        // Outside grayscale mode, the gray levels are rounded to black or white. In grayscale
+        // mode the black/white plane is the same, and the red/yellow plane marks the grays
+        assert_eq!(inky.canvas().pack()[0], 0b11111100);
+        assert_eq!(inky.canvas().pack_color()[0], 0b00000000);
+        assert_eq!(inky.canvas().pack_gray_color()[0], 0b00000110);
+
+        inky.set_grayscale(true)?;
+        inky.transport_mut().clear();
+        inky.update()?;
+        assert_eq!(sent_lut(&inky).as_deref(), Some(LUT_GRAY));
+        let events = inky.transport().events();
+        let ry = events
+            .iter()
+            .position(|e| *e == TraceEvent::Command(0x26))
+            .expect("No red/yellow plane sent");
+        assert_eq!(
+            events[ry + 1],
+            TraceEvent::Data(inky.canvas().pack_gray_color())
+        );
+
+        // Colored panels have no free RAM plane for the gray levels
+        let mut red = mock_display(DisplayModel::RedWHat)?;
+        assert!(red.set_grayscale(true).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_temperature() -> Result<()> {
+        let mut inky = mock_inky()?;
+        assert_eq!(inky.temperature_band(), TemperatureBand::Normal);
+
+        inky.transport_mut().set_read_data(vec![0x08, 0x00]);
+        assert_eq!(inky.read_temperature()?, 8.0);
+        assert_eq!(inky.temperature(), Some(8.0));
+        let events = inky.transport().events();
+        assert_eq!(events[events.len() - 2], TraceEvent::Command(0x1b));
+        assert_eq!(events[events.len() - 1], TraceEvent::Read(vec![0x08, 0x00]));
+
+        // Cold panels are not compensated for, and keep the default waveform
+        assert_eq!(inky.temperature_band(), TemperatureBand::Normal);
+        inky.transport_mut().clear();
+        inky.update()?;
+        assert_eq!(sent_lut(&inky).as_deref(), Some(LUT_BLACK));
+
+        // Hot red panels take the high temperature waveform
+        let mut red = mock_display(DisplayModel::RedWHat)?;
+        red.update()?;
+        assert_eq!(sent_lut(&red).as_deref(), Some(LUT_RED));
+        red.set_temperature(Some(35.0));
+        red.transport_mut().clear();
+        red.update()?;
+        assert_eq!(sent_lut(&red).as_deref(), Some(LUT_RED_HIGHTEMP));
+
+        // Reading fails before anything is sent over transports that cannot read
+        let mut write_only = Inky::with_transport(
+            EEPROM::for_model(DisplayModel::BlackWHat),
+            WriteOnly(MockTransport::new()),
+        )?;
+        write_only.transport_mut().0.clear();
+        assert!(write_only.read_temperature().is_err());
+        assert!(write_only.transport().0.events().is_empty());
+
+        // Reading fails on controllers without a temperature sensor
+        let mut impression = Inky::with_transport(
+            EEPROM::for_model(DisplayModel::SevenColorUc8159),
+            MockTransport::for_display(DisplayVariant::Uc8159_600x448),
+        )?;
+        assert!(impression.read_temperature().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_declared_display() -> Result<()> {
+        // A declared model is driven the same as one read from its EEPROM
+        let mut declared = mock_display(DisplayModel::BlackWHat)?;
+        declared.update()?;
+        let bytes = Vec::from(EEPROM::for_model(DisplayModel::BlackWHat));
+        let mut read =
+            Inky::with_transport(EEPROM::try_from(bytes.as_slice())?, MockTransport::new())?;
+        read.update()?;
+        read.transport().compare(&declared.transport().trace())?;
+
+        let inky = Inky::with_transport(
+            EEPROM::for_model(DisplayModel::RedWHatSsd1683),
+            MockTransport::for_display(DisplayVariant::WhatSsd1683),
+        )?;
+        assert_eq!((inky.canvas().width(), inky.canvas().height()), (400, 300));
+        assert_eq!(inky.color(), EepromColor::Red);
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder() -> Result<()> {
+        let mut inky = InkyBuilder::default()
+            .color(EepromColor::Black)
+            .transport(MockTransport::new())
+            .canvas(Canvas::new(400, 300))
+            .eeprom(EEPROM::for_model(DisplayModel::BlackWHat))
+            .build()?;
+        inky.update()?;
+        inky.transport().assert_golden(golden("what_black_blank"))
+    }
+
+    #[test]
+    fn test_update_busy_timeout() -> Result<()> {
+        let mut inky = mock_inky()?;
+        inky.transport_mut().set_busy_stuck(true);
+
+        let error = inky
+            .update()
+            .expect_err("Update with a stuck busy line succeeded");
+        let timeout = error
+            .downcast_ref::<BusyTimeout>()
+            .expect("Update failed with an error other than a timeout");
+        assert_eq!(timeout.phase(), WaitPhase::Update);
+        assert_eq!(timeout.waited(), inky.busy_timeout(WaitPhase::Update));
+
+        inky.set_busy_timeout(Some(Duration::from_secs(1)));
+        let error = inky
+            .reset()
+            .expect_err("Reset with a stuck busy line succeeded");
+        assert_eq!(
+            error.downcast_ref::<BusyTimeout>().map(|t| t.waited()),
+            Some(Duration::from_secs(1))
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "requires an Inky wHat attached"]
+    fn test_blank_hardware() -> Result<()> {
         let eeprom = EEPROM::try_new().expect("Failed to initialize eeprom");
         let mut inky = Inky::try_from(eeprom)?;
         inky.update()?;
@@ -630,7 +1670,8 @@ mod tests {
     }
 
     #[test]
-    fn test_draw_box() -> Result<()> {
+    #[ignore = "requires an Inky wHat attached"]
+    fn test_draw_box_hardware() -> Result<()> {
         let eeprom = EEPROM::try_new().expect("Failed to initialize eeprom");
         let mut inky = Inky::try_from(eeprom)?;
 
@@ -4,6 +4,12 @@
 //! to/control the screen of the Inky wHat e-ink display from Pimoroni. To get started, see the
 //! examples!
 
+pub mod asynch;
+pub mod config;
+pub mod controller;
 pub mod eeprom;
 pub mod inky;
-mod lut;
+pub mod lut;
+pub mod mock;
+pub mod simulator;
+pub mod transport;
@@ -48,6 +48,329 @@ mitigate image retention. The flashing effect is actually the ink particles bein
 the top of the display repeatedly in an attempt to reset them back into a sensible resting position.
  */
 
+use anyhow::{ensure, Context, Error, Result};
+use derive_builder::Builder;
+use num_derive::{FromPrimitive, ToPrimitive};
+use num_traits::FromPrimitive as ConvertFromPrimitive;
+use std::{fmt::Display, fs::read_to_string, ops::RangeInclusive, path::Path, time::Duration};
+
+// Number of bytes in a LUT
+pub const LUT_LEN: usize = 70;
+// Number of phases in a LUT
+pub const PHASES: usize = 7;
+// Number of voltage rows in a LUT: black, white, unused, red or yellow, and VCOM
+pub const ROWS: usize = 5;
+// Number of steps in a phase
+pub const STEPS: usize = 4;
+
+#[derive(ToPrimitive, FromPrimitive, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+/// Voltage selected for a step of a phase
+pub enum Voltage {
+    #[default]
+    Vss = 0b00,
+    Vsh1 = 0b01,
+    Vsl = 0b10,
+    Vsh2 = 0b11,
+}
+
+impl TryFrom<u8> for Voltage {
+    type Error = Error;
+
+    /// Convert a two bit voltage selection to a Voltage
+    fn try_from(value: u8) -> Result<Self> {
+        ConvertFromPrimitive::from_u8(value).context("Invalid value for voltage")
+    }
+}
+
+impl From<Voltage> for u8 {
+    /// Convert a voltage to its two bit selection
+    fn from(value: Voltage) -> Self {
+        value as u8
+    }
+}
+
+#[derive(Builder, Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// One phase of a waveform. The voltages are indexed by row, then step, and each step lasts
+/// its duration in frames. The phase is run once more for each repeat.
+pub struct Phase {
+    #[builder(default)]
+    voltages: [[Voltage; STEPS]; ROWS],
+    #[builder(default)]
+    durations: [u8; STEPS],
+    #[builder(default)]
+    repeat: u8,
+}
+
+impl Phase {
+    /// Get the voltages of each step, indexed by row then step
+    pub fn voltages(&self) -> &[[Voltage; STEPS]; ROWS] {
+        &self.voltages
+    }
+
+    /// Set the voltage of a step of a row
+    pub fn set_voltage(&mut self, row: usize, step: usize, voltage: Voltage) -> Result<()> {
+        ensure!(row < ROWS, "Row {} is out of range", row);
+        ensure!(step < STEPS, "Step {} is out of range", step);
+        self.voltages[row][step] = voltage;
+        Ok(())
+    }
+
+    /// Get the duration of each step in frames
+    pub fn durations(&self) -> &[u8; STEPS] {
+        &self.durations
+    }
+
+    /// Set the duration of each step in frames
+    pub fn set_durations(&mut self, durations: [u8; STEPS]) {
+        self.durations = durations;
+    }
+
+    /// Get how many more times the phase is run
+    pub fn repeat(&self) -> u8 {
+        self.repeat
+    }
+
+    /// Set how many more times the phase is run
+    pub fn set_repeat(&mut self, repeat: u8) {
+        self.repeat = repeat;
+    }
+
+    /// Get whether the phase is skipped because all of its steps last no time
+    pub fn is_empty(&self) -> bool {
+        self.durations.iter().all(|d| *d == 0)
+    }
+
+    /// Get how many frames the phase lasts including its repeats
+    pub fn frames(&self) -> u32 {
+        self.durations.iter().map(|d| *d as u32).sum::<u32>() * (self.repeat as u32 + 1)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// LUTs shipped with the driver
+pub enum LutPreset {
+    Black,
+    Red,
+    RedHighTemp,
+    Yellow,
+    /// Black and white without the flashing clear phases, trading ghosting for speed
+    FastBlack,
+    /// Black, white and two gray levels, for grayscale mode
+    Gray,
+}
+
+impl LutPreset {
+    /// Get the bytes of the preset LUT
+    pub fn bytes(&self) -> &'static [u8] {
+        match *self {
+            Self::Black => LUT_BLACK,
+            Self::Red => LUT_RED,
+            Self::RedHighTemp => LUT_RED_HIGHTEMP,
+            Self::Yellow => LUT_YELLOW,
+            Self::FastBlack => LUT_BLACK_FAST,
+            Self::Gray => LUT_GRAY,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// A waveform LUT for the SSD1675, in the layout described at the top of this module
+pub struct Lut {
+    phases: [Phase; PHASES],
+}
+
+impl Lut {
+    /// Create a LUT from up to seven phases. Phases that are not given are empty
+    pub fn new(phases: &[Phase]) -> Result<Self> {
+        ensure!(
+            phases.len() <= PHASES,
+            "A LUT has at most {} phases, got {}",
+            PHASES,
+            phases.len()
+        );
+        let mut lut = Self::default();
+        lut.phases[..phases.len()].copy_from_slice(phases);
+        Ok(lut)
+    }
+
+    /// Get the phases of the LUT
+    pub fn phases(&self) -> &[Phase; PHASES] {
+        &self.phases
+    }
+
+    /// Get the phases of the LUT mutably
+    pub fn phases_mut(&mut self) -> &mut [Phase; PHASES] {
+        &mut self.phases
+    }
+
+    /// Decode a preset LUT
+    pub fn preset(preset: LutPreset) -> Result<Self> {
+        Self::try_from(preset.bytes())
+    }
+
+    /// Parse a LUT from text in the layout described at the top of this module: five rows of
+    /// seven binary voltage bytes, then seven rows of four durations and a repeat count in hex.
+    /// Anything other than the values, such as row labels, is ignored
+    pub fn from_text(text: &str) -> Result<Self> {
+        let mut voltages = Vec::new();
+        let mut timings = Vec::new();
+        for token in text.split(|c: char| c == ',' || c.is_whitespace()) {
+            if let Some(bits) = token.strip_prefix("0b") {
+                ensure!(bits.len() == 8, "Voltage byte {} is not 8 bits", token);
+                voltages.push(
+                    u8::from_str_radix(bits, 2)
+                        .context(format!("Invalid voltage byte {}", token))?,
+                );
+            } else if let Some(digits) = token.strip_prefix("0x") {
+                timings.push(
+                    u8::from_str_radix(digits, 16)
+                        .context(format!("Invalid timing byte {}", token))?,
+                );
+            }
+        }
+        ensure!(
+            voltages.len() == ROWS * PHASES,
+            "Expected {} voltage bytes, got {}",
+            ROWS * PHASES,
+            voltages.len()
+        );
+        ensure!(
+            timings.len() == PHASES * (STEPS + 1),
+            "Expected {} timing bytes, got {}",
+            PHASES * (STEPS + 1),
+            timings.len()
+        );
+        voltages.extend(timings);
+        Self::try_from(voltages.as_slice())
+    }
+
+    /// Load a LUT from a text file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        Self::from_text(
+            &read_to_string(path).context(format!("Failed to read {}", path.display()))?,
+        )
+        .context(format!("Failed to load {}", path.display()))
+    }
+
+    /// Get how many frames a refresh using the LUT lasts, from the durations and repeats of
+    /// its phases
+    pub fn frames(&self) -> u32 {
+        self.phases.iter().map(Phase::frames).sum()
+    }
+
+    /// Estimate how long a refresh using the LUT takes at the frame rate the driver configures
+    pub fn estimated_refresh_time(&self) -> Duration {
+        frame_time(self.frames(), FRAME_RATE_HZ)
+    }
+}
+
+impl TryFrom<&[u8]> for Lut {
+    type Error = Error;
+
+    /// Decode a LUT from the bytes sent to the controller
+    fn try_from(value: &[u8]) -> Result<Self> {
+        ensure!(
+            value.len() == LUT_LEN,
+            "A LUT is {} bytes long, got {}",
+            LUT_LEN,
+            value.len()
+        );
+        let (voltages, timings) = value.split_at(ROWS * PHASES);
+        let mut lut = Self::default();
+        for (index, phase) in lut.phases.iter_mut().enumerate() {
+            for (row, steps) in phase.voltages.iter_mut().enumerate() {
+                let byte = voltages[row * PHASES + index];
+                for (step, voltage) in steps.iter_mut().enumerate() {
+                    // Step A is in the most significant bits
+                    *voltage = Voltage::try_from((byte >> (6 - step * 2)) & 0b11)?;
+                }
+            }
+            let timing = &timings[index * (STEPS + 1)..(index + 1) * (STEPS + 1)];
+            phase.durations.copy_from_slice(&timing[..STEPS]);
+            phase.repeat = timing[STEPS];
+        }
+        Ok(lut)
+    }
+}
+
+impl Display for Lut {
+    /// Write the LUT in the text layout read by `from_text`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bytes = Vec::<u8>::from(self);
+        let (voltages, timings) = bytes.split_at(ROWS * PHASES);
+        for row in voltages.chunks(PHASES) {
+            for byte in row {
+                write!(f, "{:#010b}, ", byte)?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(f)?;
+        for phase in timings.chunks(STEPS + 1) {
+            for byte in phase {
+                write!(f, "{:#04x}, ", byte)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<&Lut> for Vec<u8> {
+    /// Encode a LUT into the bytes sent to the controller
+    fn from(value: &Lut) -> Self {
+        let mut bytes = vec![0; LUT_LEN];
+        for (index, phase) in value.phases.iter().enumerate() {
+            for (row, steps) in phase.voltages.iter().enumerate() {
+                bytes[row * PHASES + index] = steps
+                    .iter()
+                    .enumerate()
+                    .map(|(step, voltage)| u8::from(*voltage) << (6 - step * 2))
+                    .fold(0, |byte, bits| byte | bits);
+            }
+            let timing = ROWS * PHASES + index * (STEPS + 1);
+            bytes[timing..timing + STEPS].copy_from_slice(&phase.durations);
+            bytes[timing + STEPS] = phase.repeat;
+        }
+        bytes
+    }
+}
+
+// Temperatures in degrees Celsius the panels are rated to refresh at
+pub const RATED_TEMPERATURE: RangeInclusive<f32> = 0.0..=40.0;
+// Temperatures from this up are hot, and red panels take the high temperature waveform
+pub const HOT_FROM: f32 = 30.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Temperature band of the panel, which selects the waveform used to refresh it. Cold
+/// compensation is not supported, as no cold waveform is known for the panels, so cold panels
+/// are in the normal band
+pub enum TemperatureBand {
+    Normal,
+    Hot,
+}
+
+impl TemperatureBand {
+    /// Get the band a temperature in degrees Celsius falls in
+    pub fn of(celsius: f32) -> Self {
+        if celsius >= HOT_FROM {
+            Self::Hot
+        } else {
+            Self::Normal
+        }
+    }
+}
+
+// Approximate frame rate of the controller with the gate line width and dummy line period the
+// driver configures, used to turn LUT timings into wall clock time
+pub(crate) const FRAME_RATE_HZ: u32 = 200;
+
+/// Get how long a number of frames lasts at a frame rate
+pub(crate) fn frame_time(frames: u32, frame_rate_hz: u32) -> Duration {
+    Duration::from_secs_f64(frames as f64 / frame_rate_hz as f64)
+}
+
 pub const LUT_BLACK: &[u8] = &[
     0b01001000, 0b10100000, 0b00010000, 0b00010000, 0b00010011, 0b00000000, 0b00000000, 0b01001000,
     0b10100000, 0b10000000, 0b00000000, 0b00000011, 0b00000000, 0b00000000, 0b00000000, 0b00000000,
@@ -58,6 +381,31 @@ pub const LUT_BLACK: &[u8] = &[
     0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
 ];
 
+// LUT_BLACK without the flashing phases 0 and 1, for fast refreshes that leave some ghosting.
+// Also used for partial refreshes
+pub const LUT_BLACK_FAST: &[u8] = &[
+    0b01001000, 0b10100000, 0b00010000, 0b00010000, 0b00010011, 0b00000000, 0b00000000, 0b01001000,
+    0b10100000, 0b10000000, 0b00000000, 0b00000011, 0b00000000, 0b00000000, 0b00000000, 0b00000000,
+    0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b01001000, 0b10100101, 0b00000000,
+    0b10111011, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000,
+    0b00000000, 0b00000000, 0b00000000, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x04, 0x08, 0x08, 0x10, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+// Four gray levels for grayscale mode. Phases 0 and 1 flash every pixel to white as LUT_BLACK
+// does, then phase 2 pulls pixels towards black with VSH1 for all four steps on black, the
+// first two on dark gray (LUT2) and the first only on light gray (LUT3)
+pub const LUT_GRAY: &[u8] = &[
+    0b01001000, 0b10100000, 0b01010101, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b01001000,
+    0b10100000, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b01001000, 0b10100000,
+    0b01010000, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b01001000, 0b10100000, 0b01000000,
+    0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000,
+    0b00000000, 0b00000000, 0b00000000, 0x10, 0x04, 0x04, 0x04, 0x04, 0x10, 0x04, 0x04, 0x04, 0x04,
+    0x04, 0x08, 0x08, 0x10, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
 pub const LUT_RED: &[u8] = &[
     0b01001000, 0b10100000, 0b00010000, 0b00010000, 0b00010011, 0b00000000, 0b00000000, 0b01001000,
     0b10100000, 0b10000000, 0b00000000, 0b00000011, 0b00000000, 0b00000000, 0b00000000, 0b00000000,
@@ -87,3 +435,101 @@ pub const LUT_YELLOW: &[u8] = &[
     0x08, 0x08, 0x03, 0x08, 0x20, 0x08, 0x04, 0x00, 0x00, 0x10, 0x10, 0x08, 0x08, 0x00, 0x20, 0x00,
     0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::{Lut, LutPreset, Phase, PhaseBuilder, Voltage, LUT_BLACK, LUT_RED, LUT_YELLOW};
+    use anyhow::Result;
+    use std::time::Duration;
+
+    #[test]
+    fn test_decode() -> Result<()> {
+        let lut = Lut::try_from(LUT_BLACK)?;
+        let phase = lut.phases()[0];
+        // 0b01001000 for black and white in phase 0
+        assert_eq!(
+            phase.voltages()[0],
+            [Voltage::Vsh1, Voltage::Vss, Voltage::Vsl, Voltage::Vss]
+        );
+        assert_eq!(phase.durations(), &[0x10, 0x04, 0x04, 0x04]);
+        assert_eq!(phase.repeat(), 0x04);
+        assert!(!lut.phases()[2].is_empty());
+        assert!(lut.phases()[3].is_empty());
+        assert_eq!(lut.frames(), 892);
+        // 892 frames at 200Hz
+        assert_eq!(lut.estimated_refresh_time(), Duration::from_millis(4460));
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        for bytes in [LUT_BLACK, LUT_RED, LUT_YELLOW] {
+            assert_eq!(Vec::<u8>::from(&Lut::try_from(bytes)?), bytes);
+        }
+        // Every preset decodes, so its refresh time can be estimated
+        for preset in [
+            LutPreset::Black,
+            LutPreset::Red,
+            LutPreset::RedHighTemp,
+            LutPreset::Yellow,
+            LutPreset::FastBlack,
+            LutPreset::Gray,
+        ] {
+            Lut::preset(preset)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_validation() -> Result<()> {
+        assert!(Lut::try_from(&LUT_BLACK[..69]).is_err());
+        assert!(Voltage::try_from(0b100).is_err());
+        assert!(Lut::new(&[Phase::default(); 8]).is_err());
+
+        let mut phase = PhaseBuilder::default()
+            .durations([0x20, 0, 0, 0])
+            .repeat(1)
+            .build()?;
+        phase.set_voltage(4, 3, Voltage::Vsh2)?;
+        assert!(phase.set_voltage(5, 0, Voltage::Vsh2).is_err());
+        let bytes = Vec::<u8>::from(&Lut::new(&[phase])?);
+        assert_eq!((bytes[28], bytes[35], bytes[39]), (0b00000011, 0x20, 1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_text() -> Result<()> {
+        // The example from the module documentation, with its labels
+        let text = "
+    Phase 0     Phase 1     Phase 2     Phase 3     Phase 4     Phase 5     Phase 6
+    A B C D
+0b01001000, 0b10100000, 0b00010000, 0b00010000, 0b00010011, 0b00000000, 0b00000000,  LUT0 - Black
+0b01001000, 0b10100000, 0b10000000, 0b00000000, 0b00000011, 0b00000000, 0b00000000,  LUT1 - White
+0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000,  NOT USED BY HARDWARE
+0b01001000, 0b10100101, 0b00000000, 0b10111011, 0b00000000, 0b00000000, 0b00000000,  LUT3 - Yellow or Red
+0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000,  LUT4 - VCOM
+
+    Duration                Repeat
+    A     B     C     D
+0x10, 0x04, 0x04, 0x04, 0x04,  <-- Timings for Phase 0
+0x10, 0x04, 0x04, 0x04, 0x04,  <-- Timings for Phase 1
+0x04, 0x08, 0x08, 0x10, 0x10,      etc
+0x00, 0x00, 0x00, 0x00, 0x00,
+0x00, 0x00, 0x00, 0x00, 0x00,
+0x00, 0x00, 0x00, 0x00, 0x00,
+0x00, 0x00, 0x00, 0x00, 0x00,
+";
+        let lut = Lut::from_text(text)?;
+        assert_eq!(lut, Lut::preset(LutPreset::Black)?);
+        assert_eq!(Lut::from_text(&lut.to_string())?, lut);
+
+        Lut::preset(LutPreset::Gray)?;
+        let fast = Lut::preset(LutPreset::FastBlack)?;
+        assert!(fast.phases()[..2].iter().all(Phase::is_empty));
+        assert!(fast.estimated_refresh_time() < lut.estimated_refresh_time());
+
+        assert!(Lut::from_text(&text.replace("0b00010011", "0b0001001")).is_err());
+        assert!(Lut::from_text(&text.replace("0x10, 0x10,", "0x10,")).is_err());
+        Ok(())
+    }
+}
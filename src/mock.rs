@@ -0,0 +1,448 @@
+//! Recording mock transport and golden SPI traces
+//!
+//! [`MockTransport`] records everything the driver sends instead of talking to hardware, so
+//! command sequences can be checked without a panel attached. A recorded trace renders to a
+//! line based text format that can be checked in as a golden file:
+//!
+//! ```text
+//! reset
+//! cmd 0x12 SoftReset
+//! wait reset
+//! cmd 0x01 GateSetting
+//! data [0x2c, 0x01, 0x00]
+//! data 15000 bytes fnv1a=0xce4146cbed57beed
+//! ```
+//!
+//! `cmd` lines are bytes written with the DC line low and `data` lines are payloads written
+//! with the DC line high. Payloads longer than [`MockTransport::INLINE_DATA_LEN`] bytes are
+//! summarized by their length and FNV-1a hash. Commands are named from the command set of the
+//! controller given to [`MockTransport::for_display`]. Blank lines and lines starting with `#`
+//! are ignored when comparing.
+
+use crate::{
+    asynch::AsyncDisplayTransport,
+    controller::{self, Controller},
+    eeprom::DisplayVariant,
+    transport::{BusyWait, DisplayTransport, WaitPhase},
+};
+use anyhow::{bail, Context, Result};
+use std::{env::var_os, fs, path::Path, time::Duration};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A single interaction with the display hardware
+pub enum TraceEvent {
+    /// The reset line was pulsed
+    Reset,
+    /// A command byte was written with the DC line low
+    Command(u8),
+    /// A payload was written with the DC line high
+    Data(Vec<u8>),
+    /// The busy line was waited on
+    Wait(WaitPhase),
+    /// A payload was read with the DC line high
+    Read(Vec<u8>),
+}
+
+impl TraceEvent {
+    /// Render the event as one line of a golden trace, naming commands with `command_name`
+    fn render(&self, command_name: impl Fn(u8) -> Option<String>) -> String {
+        match self {
+            Self::Reset => "reset".to_string(),
+            Self::Wait(phase) => format!("wait {}", phase),
+            Self::Command(command) => match command_name(*command) {
+                Some(name) => format!("cmd {:#04x} {}", command, name),
+                None => format!("cmd {:#04x}", command),
+            },
+            Self::Data(data) if data.len() <= MockTransport::INLINE_DATA_LEN => {
+                format!("data {}", hex_list(data))
+            }
+            Self::Data(data) => format!("data {} bytes fnv1a={:#018x}", data.len(), fnv1a(data)),
+            Self::Read(data) => format!("read {}", hex_list(data)),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Transport that records every event instead of driving hardware
+pub struct MockTransport {
+    events: Vec<TraceEvent>,
+    busy_stuck: bool,
+    read_data: Vec<u8>,
+    variant: DisplayVariant,
+}
+
+impl Default for MockTransport {
+    fn default() -> Self {
+        Self {
+            events: Vec::new(),
+            busy_stuck: false,
+            read_data: Vec::new(),
+            variant: DisplayVariant::What,
+        }
+    }
+}
+
+impl MockTransport {
+    // Payloads up to this many bytes are written out in full in golden traces
+    pub const INLINE_DATA_LEN: usize = 32;
+    // Set this environment variable to rewrite golden files instead of comparing against them
+    pub const UPDATE_GOLDEN_VAR: &'static str = "INKY_UPDATE_GOLDEN";
+
+    /// Create a mock transport with an empty trace, naming commands as SSD1675 commands
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a mock transport with an empty trace, naming commands from the command set of
+    /// the controller used by a display
+    pub fn for_display(variant: DisplayVariant) -> Self {
+        Self {
+            variant,
+            ..Self::default()
+        }
+    }
+
+    /// Get the controller whose command set names the recorded commands
+    fn controller(&self) -> &dyn Controller<Self> {
+        controller::for_variant(self.variant)
+    }
+
+    /// Get the events recorded so far
+    pub fn events(&self) -> &[TraceEvent] {
+        &self.events
+    }
+
+    /// Record an event
+    pub(crate) fn record(&mut self, event: TraceEvent) {
+        self.events.push(event);
+    }
+
+    /// Simulate a busy line that never falls, so every wait times out
+    pub fn set_busy_stuck(&mut self, stuck: bool) {
+        self.busy_stuck = stuck;
+    }
+
+    /// Set the bytes the controller answers reads with. Reads past the end return zeroes
+    pub fn set_read_data(&mut self, data: Vec<u8>) {
+        self.read_data = data;
+    }
+
+    /// Record a wait, timing out if the busy line is stuck
+    fn record_wait(&mut self, wait: &BusyWait) -> Result<()> {
+        self.record(TraceEvent::Wait(wait.phase()));
+        if self.busy_stuck {
+            return Err(wait.timed_out(wait.timeout()).into());
+        }
+        Ok(())
+    }
+
+    /// Forget the events recorded so far
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    /// Render the recorded events in the golden trace format
+    pub fn trace(&self) -> String {
+        self.events
+            .iter()
+            .map(|e| e.render(|c| self.controller().command_name(c)) + "\n")
+            .collect::<String>()
+    }
+
+    /// Compare the recorded events against a golden trace, failing with a description of
+    /// every difference
+    pub fn compare(&self, golden: &str) -> Result<()> {
+        let differences = diff_traces(&self.trace(), golden);
+        if !differences.is_empty() {
+            bail!("Trace does not match golden:\n{}", differences.join("\n"));
+        }
+        Ok(())
+    }
+
+    /// Compare the recorded events against a golden trace file. If the `INKY_UPDATE_GOLDEN`
+    /// environment variable is set, the file is rewritten with the recorded trace instead.
+    pub fn assert_golden<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if var_os(Self::UPDATE_GOLDEN_VAR).is_some() {
+            fs::write(path, self.trace())
+                .context(format!("Failed to write golden trace {}", path.display()))?;
+            return Ok(());
+        }
+        let golden = fs::read_to_string(path)
+            .context(format!("Failed to read golden trace {}", path.display()))?;
+        self.compare(&golden)
+            .context(format!("Golden trace {} differs", path.display()))
+    }
+}
+
+impl DisplayTransport for MockTransport {
+    fn send_command(&mut self, command: u8) -> Result<()> {
+        self.record(TraceEvent::Command(command));
+        Ok(())
+    }
+
+    fn send_data(&mut self, data: &[u8]) -> Result<()> {
+        self.record(TraceEvent::Data(data.to_vec()));
+        Ok(())
+    }
+
+    fn read_data(&mut self, len: usize) -> Result<Vec<u8>> {
+        let mut data = self
+            .read_data
+            .drain(..len.min(self.read_data.len()))
+            .collect::<Vec<_>>();
+        data.resize(len, 0);
+        self.record(TraceEvent::Read(data.clone()));
+        Ok(data)
+    }
+
+    fn can_read(&self) -> bool {
+        true
+    }
+
+    fn holds_cs(&self) -> bool {
+        // There is no bus, so every packet reaches the trace whole
+        true
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.record(TraceEvent::Reset);
+        Ok(())
+    }
+
+    fn wait(&mut self, wait: &BusyWait) -> Result<()> {
+        self.record_wait(wait)
+    }
+
+    fn delay(&mut self, _duration: Duration) {}
+}
+
+impl AsyncDisplayTransport for MockTransport {
+    async fn send_command(&mut self, command: u8) -> Result<()> {
+        self.record(TraceEvent::Command(command));
+        Ok(())
+    }
+
+    async fn send_data(&mut self, data: &[u8]) -> Result<()> {
+        self.record(TraceEvent::Data(data.to_vec()));
+        Ok(())
+    }
+
+    fn holds_cs(&self) -> bool {
+        true
+    }
+
+    async fn reset(&mut self) -> Result<()> {
+        self.record(TraceEvent::Reset);
+        Ok(())
+    }
+
+    async fn wait(&mut self, wait: &BusyWait) -> Result<()> {
+        self.record_wait(wait)
+    }
+
+    async fn delay(&mut self, _duration: Duration) {}
+}
+
+/// A non-data line of a trace and the data lines that followed it
+struct Packet<'a> {
+    header: &'a str,
+    data: Vec<&'a str>,
+}
+
+impl Packet<'_> {
+    /// Name used to refer to the packet in differences, the command name if there is one
+    fn name(&self) -> &str {
+        self.header.rsplit(' ').next().unwrap_or(self.header)
+    }
+}
+
+/// Split a rendered trace into packets, skipping blank and comment lines
+fn packets(trace: &str) -> Vec<Packet<'_>> {
+    let mut packets: Vec<Packet> = Vec::new();
+    for line in trace
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+    {
+        match (line.strip_prefix("data "), packets.last_mut()) {
+            (Some(data), Some(packet)) => packet.data.push(data),
+            _ => packets.push(Packet {
+                header: line,
+                data: Vec::new(),
+            }),
+        }
+    }
+    packets
+}
+
+/// Describe the differences between a recorded trace and a golden trace, one per packet.
+/// The traces are aligned on their longest common run of packet headers first, so a packet
+/// that was added or dropped is reported on its own rather than shifting every packet after it
+pub fn diff_traces(recorded: &str, golden: &str) -> Vec<String> {
+    let recorded = packets(recorded);
+    let golden = packets(golden);
+
+    // common[r][g] is the length of the longest common subsequence of the headers from
+    // recorded[r..] and golden[g..]
+    let mut common = vec![vec![0usize; golden.len() + 1]; recorded.len() + 1];
+    for r in (0..recorded.len()).rev() {
+        for g in (0..golden.len()).rev() {
+            common[r][g] = if recorded[r].header == golden[g].header {
+                common[r + 1][g + 1] + 1
+            } else {
+                common[r + 1][g].max(common[r][g + 1])
+            };
+        }
+    }
+
+    let mut differences = Vec::new();
+    let (mut extra, mut missing) = (Vec::new(), Vec::new());
+    let (mut r, mut g) = (0, 0);
+    while r < recorded.len() || g < golden.len() {
+        if r < recorded.len() && g < golden.len() && recorded[r].header == golden[g].header {
+            differences.extend(unmatched(&recorded, &golden, &extra, &missing));
+            (extra, missing) = (Vec::new(), Vec::new());
+            if recorded[r].data != golden[g].data {
+                differences.push(format!(
+                    "{} data differs: recorded {} vs golden {}",
+                    recorded[r].name(),
+                    recorded[r].data.join(" "),
+                    golden[g].data.join(" ")
+                ));
+            }
+            r += 1;
+            g += 1;
+        } else if g == golden.len() || (r < recorded.len() && common[r + 1][g] >= common[r][g + 1])
+        {
+            extra.push(r);
+            r += 1;
+        } else {
+            missing.push(g);
+            g += 1;
+        }
+    }
+    differences.extend(unmatched(&recorded, &golden, &extra, &missing));
+
+    differences
+}
+
+/// Describe a run of packets between two aligned ones that only one of the traces has. Runs in
+/// both traces are paired up as replaced packets, and the rest are extra or missing
+fn unmatched(
+    recorded: &[Packet],
+    golden: &[Packet],
+    extra: &[usize],
+    missing: &[usize],
+) -> Vec<String> {
+    let mut differences: Vec<String> = extra
+        .iter()
+        .zip(missing)
+        .map(|(&r, &g)| {
+            format!(
+                "packet {}: recorded `{}` vs golden `{}`",
+                g, recorded[r].header, golden[g].header
+            )
+        })
+        .collect();
+    differences.extend(extra.iter().skip(missing.len()).map(|&r| {
+        format!(
+            "packet {}: recorded `{}` is not in the golden trace",
+            r, recorded[r].header
+        )
+    }));
+    differences.extend(missing.iter().skip(extra.len()).map(|&g| {
+        format!(
+            "packet {}: golden `{}` was not recorded",
+            g, golden[g].header
+        )
+    }));
+    differences
+}
+
+/// Format bytes as a list of hex values
+fn hex_list(data: &[u8]) -> String {
+    format!(
+        "[{}]",
+        data.iter()
+            .map(|b| format!("{:#04x}", b))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+/// 64-bit FNV-1a hash, used to summarize large payloads
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff_traces, MockTransport, TraceEvent};
+    use crate::transport::{BusyWait, DisplayTransport, WaitPhase};
+    use anyhow::Result;
+    use std::time::Duration;
+
+    #[test]
+    fn test_render_trace() -> Result<()> {
+        let mut mock = MockTransport::new();
+        mock.reset()?;
+        mock.send_command(0x01)?;
+        mock.send_data(&[0x2c, 0x01, 0x00])?;
+        mock.send_data(&[0xff; 64])?;
+        mock.wait(&BusyWait::new(WaitPhase::Update, Duration::from_secs(1)))?;
+
+        assert_eq!(mock.events()[0], TraceEvent::Reset);
+        assert_eq!(
+            mock.trace(),
+            "reset\n\
+             cmd 0x01 GateSetting\n\
+             data [0x2c, 0x01, 0x00]\n\
+             data 64 bytes fnv1a=0x84cc4da0e20ecde5\n\
+             wait update\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_traces() {
+        let golden = "# wHat gate setting\ncmd 0x01 GateSetting\ndata [0x2c, 0x01, 0x00]\n";
+
+        assert!(diff_traces(golden, golden).is_empty());
+        assert_eq!(
+            diff_traces("cmd 0x01 GateSetting\ndata [0x2b, 0x01, 0x00]\n", golden),
+            vec![
+                "GateSetting data differs: recorded [0x2b, 0x01, 0x00] vs golden [0x2c, 0x01, 0x00]"
+            ]
+        );
+        assert_eq!(
+            diff_traces("", golden),
+            vec!["packet 0: golden `cmd 0x01 GateSetting` was not recorded"]
+        );
+
+        // A packet added before others only reports itself, and later differences are still
+        // found against the right packets
+        let golden = "reset\ncmd 0x01 GateSetting\ndata [0x2c]\ncmd 0x20 TriggerDisplayUpdate\n";
+        assert_eq!(
+            diff_traces(
+                "reset\ncmd 0x12 SoftReset\ncmd 0x01 GateSetting\ndata [0x2b]\n\
+                 cmd 0x20 TriggerDisplayUpdate\n",
+                golden
+            ),
+            vec![
+                "packet 1: recorded `cmd 0x12 SoftReset` is not in the golden trace",
+                "GateSetting data differs: recorded [0x2b] vs golden [0x2c]",
+            ]
+        );
+        assert_eq!(
+            diff_traces("reset\ncmd 0x20 TriggerDisplayUpdate\n", golden),
+            vec!["packet 1: golden `cmd 0x01 GateSetting` was not recorded"]
+        );
+        assert_eq!(
+            diff_traces("reset\ncmd 0x22 DisplayUpdateSequence\ncmd 0x20 TriggerDisplayUpdate\n", golden),
+            vec!["packet 1: recorded `cmd 0x22 DisplayUpdateSequence` vs golden `cmd 0x01 GateSetting`"]
+        );
+    }
+}
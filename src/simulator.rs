@@ -0,0 +1,468 @@
+//! Simulated display that renders to an image file
+//!
+//! [`SimulatorTransport`] decodes the same SPI traffic a real panel receives and, every time
+//! the driver triggers a display update, renders the display RAM to an image. Because it
+//! works from the packed RAM planes rather than the canvas, the output shows any orientation
+//! or packing problems in what the driver sends. Buffers are decoded in the order the driver
+//! packs them, column by column through the RAM window, and written at the RAM pointers. The
+//! RAM keeps its contents between updates, so a region update only changes the window it
+//! writes. Given the [`Layout`] of the panel, the RAM is mapped back
+//! to the way the panel is mounted. Images are written as PNG or binary PPM depending on the
+//! file extension.
+//!
+//! Pixels are decoded from the bits of both RAM planes as black, white or the panel's color.
+//! If the uploaded LUT is the grayscale one, the planes are decoded as four gray levels
+//! instead.
+
+use crate::{
+    eeprom::{DisplayVariant, EEPROM},
+    inky::{Color, Command, Inky, Layout},
+    lut::LUT_GRAY,
+    transport::{BusyWait, DisplayTransport},
+};
+use anyhow::{bail, ensure, Context, Result};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+#[derive(Debug, Clone)]
+/// The contents of the display after an update
+pub struct Frame {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl Frame {
+    /// Get the width of the frame
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Get the height of the frame
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Get the color shown at a given pixel
+    pub fn get_pixel(&self, col: usize, row: usize) -> Color {
+        self.pixels[row * self.width + col]
+    }
+
+    /// Get the frame as RGB bytes, row by row
+    pub fn rgb(&self) -> Vec<u8> {
+        self.pixels.iter().flat_map(|p| p.rgb()).collect()
+    }
+
+    /// Write the frame to an image file, as PNG if the path ends in `.png` and as binary PPM
+    /// otherwise
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let file = File::create(path).context(format!("Failed to create {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+
+        if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("png"))
+        {
+            let mut encoder = png::Encoder::new(writer, self.width as u32, self.height as u32);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.write_header()?.write_image_data(&self.rgb())?;
+        } else {
+            write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+            writer.write_all(&self.rgb())?;
+            writer.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Transport that renders the display RAM to an image instead of driving hardware
+pub struct SimulatorTransport {
+    path: PathBuf,
+    color: Color,
+    layout: Option<Layout>,
+    command: Option<u8>,
+    ram_x: Vec<u8>,
+    pointer: (usize, usize),
+    bw: Vec<Vec<u8>>,
+    ry: Vec<Vec<u8>>,
+    lut: Vec<u8>,
+    frame: Option<Frame>,
+    frames: usize,
+}
+
+impl SimulatorTransport {
+    /// Create a simulator that writes each displayed frame to `path`, showing pixels set in
+    /// the color RAM plane as `color`
+    pub fn new<P: AsRef<Path>>(path: P, color: Color) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            color,
+            layout: None,
+            command: None,
+            ram_x: Vec::new(),
+            pointer: (0, 0),
+            bw: Vec::new(),
+            ry: Vec::new(),
+            lut: Vec::new(),
+            frame: None,
+            frames: 0,
+        }
+    }
+
+    /// Show the display RAM as placed on the panel by a layout, rather than as stored
+    pub fn with_layout(mut self, layout: Layout) -> Self {
+        self.bw = vec![vec![1; layout.ram_width()]; layout.ram_height()];
+        self.ry = vec![vec![0; layout.ram_width()]; layout.ram_height()];
+        self.layout = Some(layout);
+        self
+    }
+
+    /// Get the most recently displayed frame
+    pub fn frame(&self) -> Option<&Frame> {
+        self.frame.as_ref()
+    }
+
+    /// Get the number of frames displayed so far
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Get whether the uploaded LUT is the one used in grayscale mode
+    fn grayscale(&self) -> bool {
+        self.lut == LUT_GRAY
+    }
+
+    /// Write a buffer to a plane, starting at the RAM pointer. The bits are decoded in the
+    /// order the driver packs them: column by column through the RAM window, least
+    /// significant bit first. The window is as wide as the RAM X window and as tall as the
+    /// buffer needs. RAM outside of a layout is ignored, and without one the RAM grows to hold
+    /// the data.
+    fn write_buffer(&mut self, data: &[u8], ry: bool) -> Result<()> {
+        let [x_start, x_end] = self.ram_x[..] else {
+            bail!("RAM X window was not set before writing to the display RAM");
+        };
+        ensure!(x_end >= x_start, "RAM X window ends before it starts");
+        let width = (x_end - x_start) as usize * 8 + 8;
+        let bits = data.len() * 8;
+        ensure!(
+            bits.is_multiple_of(width),
+            "{} bytes of RAM data do not fill whole rows of a {} pixel wide window",
+            data.len(),
+            width
+        );
+        let height = bits / width;
+
+        let (first_col, first_row) = (self.pointer.0 * 8, self.pointer.1);
+        let growable = self.layout.is_none();
+        let (plane, fill) = if ry {
+            (&mut self.ry, 0)
+        } else {
+            (&mut self.bw, 1)
+        };
+        for i in 0..bits {
+            let (col, row) = (first_col + i / height, first_row + i % height);
+            if growable {
+                if plane.len() <= row {
+                    plane.resize(row + 1, Vec::new());
+                }
+                if plane[row].len() <= col {
+                    plane[row].resize(col + 1, fill);
+                }
+            }
+            if let Some(pixel) = plane.get_mut(row).and_then(|r| r.get_mut(col)) {
+                *pixel = (data[i / 8] >> (i % 8)) & 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Decode the RAM planes into the frame the panel would show
+    fn render(&self) -> Result<Frame> {
+        let ram_width = self
+            .bw
+            .iter()
+            .chain(&self.ry)
+            .map(Vec::len)
+            .max()
+            .unwrap_or(0);
+        ensure!(
+            ram_width > 0,
+            "Display RAM was not written before the display update"
+        );
+
+        let ram_height = self.bw.len().max(self.ry.len());
+        let bit = |plane: &[Vec<u8>], i: usize| {
+            plane
+                .get(i / ram_width)
+                .and_then(|r| r.get(i % ram_width))
+                .copied()
+        };
+        let grayscale = self.grayscale();
+        let ram_pixels =
+            (0..ram_width * ram_height).map(|i| match (bit(&self.bw, i), bit(&self.ry, i)) {
+                (Some(0), Some(1)) if grayscale => Color::DarkGray,
+                (_, Some(1)) if grayscale => Color::LightGray,
+                (_, Some(1)) => self.color,
+                (Some(0), _) => Color::Black,
+                _ => Color::White,
+            });
+
+        let Some(layout) = self.layout else {
+            return Ok(Frame {
+                width: ram_width,
+                height: ram_height,
+                pixels: ram_pixels.collect(),
+            });
+        };
+
+        let (width, height) = (layout.width(), layout.height());
+        let mut pixels = vec![Color::White; width * height];
+        for (i, color) in ram_pixels.enumerate() {
+            if let Some((col, row)) = layout.ram_to_panel(i % ram_width, i / ram_width) {
+                pixels[row * width + col] = color;
+            }
+        }
+
+        Ok(Frame {
+            width,
+            height,
+            pixels,
+        })
+    }
+}
+
+impl DisplayTransport for SimulatorTransport {
+    fn send_command(&mut self, command: u8) -> Result<()> {
+        self.command = Some(command);
+        match Command::try_from(command) {
+            Ok(Command::SetRamXStartEnd) => self.ram_x.clear(),
+            Ok(Command::SetLUT) => self.lut.clear(),
+            Ok(Command::TriggerDisplayUpdate) => {
+                let frame = self.render()?;
+                frame.save(&self.path)?;
+                self.frame = Some(frame);
+                self.frames += 1;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn send_data(&mut self, data: &[u8]) -> Result<()> {
+        let Some(command) = self.command else {
+            bail!("Data sent to the simulator before any command");
+        };
+        match Command::try_from(command) {
+            Ok(Command::SetRamXStartEnd) => self.ram_x.extend_from_slice(data),
+            Ok(Command::SetRamXPointerStart) => {
+                let [col] = data[..] else {
+                    bail!("RAM X pointer takes one byte, got {}", data.len());
+                };
+                self.pointer.0 = col as usize;
+            }
+            Ok(Command::SetRamYPointerStart) => {
+                let [lo, hi] = data[..] else {
+                    bail!("RAM Y pointer takes two bytes, got {}", data.len());
+                };
+                self.pointer.1 = u16::from_le_bytes([lo, hi]) as usize;
+            }
+            Ok(command @ (Command::SetBWBuffer | Command::SetRYBuffer)) => {
+                self.write_buffer(data, matches!(command, Command::SetRYBuffer))?
+            }
+            Ok(Command::SetLUT) => self.lut.extend_from_slice(data),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.command = None;
+        Ok(())
+    }
+
+    fn wait(&mut self, _wait: &BusyWait) -> Result<()> {
+        Ok(())
+    }
+
+    fn delay(&mut self, _duration: Duration) {}
+}
+
+impl Inky<SimulatorTransport> {
+    /// Create a simulated display for the panel described by an EEPROM, which writes each
+    /// update to an image file at `path`. Only panels with an SSD1675 controller can be
+    /// simulated
+    pub fn simulated<P: AsRef<Path>>(eeprom: EEPROM, path: P) -> Result<Self> {
+        let variant = eeprom.display_variant();
+        ensure!(
+            matches!(variant, DisplayVariant::What | DisplayVariant::Phat),
+            "The simulator only decodes SSD1675 commands, not those of the {:?} display",
+            variant
+        );
+        let color = eeprom.color().try_into()?;
+        let layout = Self::layout(&eeprom);
+        Self::with_transport(
+            eeprom,
+            SimulatorTransport::new(path, color).with_layout(layout),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        eeprom::{DisplayModel, EEPROM},
+        inky::{Color, Inky, Line, Rectangle},
+    };
+    use anyhow::Result;
+    use std::{env::temp_dir, fs};
+
+    #[test]
+    fn test_simulate_line() -> Result<()> {
+        let path = temp_dir().join(format!("inky-simulator-{}.ppm", std::process::id()));
+        let mut inky = Inky::simulated(EEPROM::for_model(DisplayModel::BlackWHat), &path)?;
+
+        // A horizontal line along the second row, drawn as (row, col) coordinates
+        inky.canvas_mut().draw(Line::new((1, 0), (1, 9)));
+        inky.update()?;
+
+        let frame = inky.transport().frame().expect("No frame was rendered");
+        assert_eq!((frame.width(), frame.height()), (400, 300));
+        assert_eq!(frame.get_pixel(0, 1), Color::Black);
+        assert_eq!(frame.get_pixel(9, 1), Color::Black);
+        assert_eq!(frame.get_pixel(10, 1), Color::White);
+        assert_eq!(frame.get_pixel(0, 0), Color::White);
+
+        let image = fs::read(&path)?;
+        fs::remove_file(&path)?;
+        assert!(image.starts_with(b"P6\n400 300\n255\n"));
+        assert_eq!(image.len(), 15 + 400 * 300 * 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_simulate_phat() -> Result<()> {
+        let path = temp_dir().join(format!("inky-simulator-phat-{}.ppm", std::process::id()));
+        let mut inky = Inky::simulated(EEPROM::for_model(DisplayModel::BlackPHat), &path)?;
+
+        inky.canvas_mut().draw(Line::new((1, 0), (1, 9)));
+        inky.update()?;
+        fs::remove_file(&path)?;
+
+        // The rotated RAM is shown the way the panel is mounted
+        let frame = inky.transport().frame().expect("No frame was rendered");
+        assert_eq!((frame.width(), frame.height()), (212, 104));
+        assert_eq!(frame.get_pixel(0, 1), Color::Black);
+        assert_eq!(frame.get_pixel(9, 1), Color::Black);
+        assert_eq!(frame.get_pixel(10, 1), Color::White);
+        assert_eq!(frame.get_pixel(0, 0), Color::White);
+        Ok(())
+    }
+
+    #[test]
+    fn test_simulate_red() -> Result<()> {
+        let path = temp_dir().join(format!("inky-simulator-red-{}.png", std::process::id()));
+        let mut inky = Inky::simulated(EEPROM::for_model(DisplayModel::RedWHat), &path)?;
+
+        inky.canvas_mut().draw(Line::new((0, 0), (0, 9)));
+        inky.canvas_mut()
+            .draw_color(Line::new((1, 0), (1, 9)), Color::Red);
+        inky.update()?;
+
+        let frame = inky.transport().frame().expect("No frame was rendered");
+        assert_eq!(frame.get_pixel(5, 0), Color::Black);
+        assert_eq!(frame.get_pixel(5, 1), Color::Red);
+        assert_eq!(frame.get_pixel(5, 2), Color::White);
+
+        let image = fs::read(&path)?;
+        fs::remove_file(&path)?;
+        assert!(image.starts_with(b"\x89PNG"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_simulate_other_controller() {
+        let path = temp_dir().join(format!("inky-simulator-uc8159-{}.ppm", std::process::id()));
+        assert!(Inky::simulated(EEPROM::for_model(DisplayModel::SevenColorUc8159), &path).is_err());
+    }
+
+    #[test]
+    fn test_simulate_region() -> Result<()> {
+        let path = temp_dir().join(format!("inky-simulator-region-{}.ppm", std::process::id()));
+        let mut inky = Inky::simulated(EEPROM::for_model(DisplayModel::BlackWHat), &path)?;
+
+        // Lines in rows that the region shares, to the left of and inside its RAM window
+        inky.canvas_mut().draw(Line::new((12, 0), (12, 9)));
+        inky.canvas_mut().draw(Line::new((14, 16), (14, 31)));
+        inky.update()?;
+
+        // Erase the canvas, then update only the region, which is written as the whole bytes
+        // of columns 16 to 31
+        inky.canvas_mut()
+            .draw_color(Rectangle::new((0, 0), (299, 399)), Color::White);
+        inky.canvas_mut().draw(Rectangle::new((10, 20), (19, 29)));
+        inky.update_region(Rectangle::new((10, 20), (19, 29)))?;
+        fs::remove_file(&path)?;
+
+        let frame = inky.transport().frame().expect("No frame was rendered");
+        assert_eq!(inky.transport().frames(), 2);
+        // Inside the region
+        assert_eq!(frame.get_pixel(20, 10), Color::Black);
+        assert_eq!(frame.get_pixel(29, 19), Color::Black);
+        // Inside the RAM window but outside the region, written from the erased canvas
+        assert_eq!(frame.get_pixel(16, 14), Color::White);
+        assert_eq!(frame.get_pixel(31, 14), Color::White);
+        // Outside the RAM window, kept from the first update
+        assert_eq!(frame.get_pixel(0, 12), Color::Black);
+        assert_eq!(frame.get_pixel(9, 12), Color::Black);
+        assert_eq!(frame.get_pixel(10, 12), Color::White);
+        Ok(())
+    }
+
+    #[test]
+    fn test_simulate_yellow() -> Result<()> {
+        let path = temp_dir().join(format!("inky-simulator-yellow-{}.ppm", std::process::id()));
+        let mut inky = Inky::simulated(EEPROM::for_model(DisplayModel::YellowWHat), &path)?;
+
+        // The yellow LUT drives the yellow pixels with the same row a grayscale LUT uses
+        inky.canvas_mut()
+            .draw_color(Line::new((0, 0), (0, 9)), Color::Yellow);
+        inky.update()?;
+        fs::remove_file(&path)?;
+
+        let frame = inky.transport().frame().expect("No frame was rendered");
+        assert_eq!(frame.get_pixel(5, 0), Color::Yellow);
+        assert_eq!(frame.get_pixel(5, 1), Color::White);
+        Ok(())
+    }
+
+    #[test]
+    fn test_simulate_gray() -> Result<()> {
+        let path = temp_dir().join(format!("inky-simulator-gray-{}.ppm", std::process::id()));
+        let mut inky = Inky::simulated(EEPROM::for_model(DisplayModel::BlackWHat), &path)?;
+        inky.set_grayscale(true)?;
+
+        for (row, color) in [Color::Black, Color::DarkGray, Color::LightGray]
+            .into_iter()
+            .enumerate()
+        {
+            inky.canvas_mut()
+                .draw_color(Line::new((row as isize, 0), (row as isize, 9)), color);
+        }
+        inky.update()?;
+        fs::remove_file(&path)?;
+
+        let frame = inky.transport().frame().expect("No frame was rendered");
+        assert_eq!(frame.get_pixel(5, 0), Color::Black);
+        assert_eq!(frame.get_pixel(5, 1), Color::DarkGray);
+        assert_eq!(frame.get_pixel(5, 2), Color::LightGray);
+        assert_eq!(frame.get_pixel(5, 3), Color::White);
+        Ok(())
+    }
+}
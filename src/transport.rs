@@ -0,0 +1,685 @@
+//! Hardware transports used to talk to the Inky display controller
+//!
+//! The display controller is driven over a write-only SPI bus and three GPIO lines: a
+//! data/command (DC) select line, a reset line, and a busy line the controller holds while it
+//! is working. [`DisplayTransport`] abstracts those, so the command sequences in
+//! [`crate::inky`] can run against the Raspberry Pi hardware or anything else that implements
+//! it. [`RppalTransport`] drives the Raspberry Pi directly, and [`HalTransport`] drives any
+//! board with `embedded-hal` 1.0 implementations of the bus and pins.
+
+use crate::config::InkyConfig;
+use anyhow::{anyhow, bail, ensure, Result};
+use embedded_hal::{
+    delay::DelayNs,
+    digital::{InputPin as HalInputPin, OutputPin as HalOutputPin},
+    spi::SpiDevice,
+};
+use rppal::{
+    gpio::{Gpio, InputPin, OutputPin, Trigger},
+    spi::{Mode, Spi},
+};
+use std::{
+    error::Error,
+    fmt::Display,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The part of the display lifecycle a busy wait happens in
+pub enum WaitPhase {
+    /// Waiting for the controller to come out of a reset
+    Reset,
+    /// Waiting for a display refresh to finish
+    Update,
+    /// Waiting for the controller to enter deep sleep
+    DeepSleep,
+    /// Waiting for the controller to power on the panel
+    PowerOn,
+    /// Waiting for the controller to power off the panel
+    PowerOff,
+    /// Waiting for the controller to measure the panel temperature
+    Temperature,
+}
+
+impl Display for WaitPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Self::Reset => "reset",
+                Self::Update => "update",
+                Self::DeepSleep => "deep sleep",
+                Self::PowerOn => "power on",
+                Self::PowerOff => "power off",
+                Self::Temperature => "temperature",
+            }
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A wait for the controller to release the busy line
+pub struct BusyWait {
+    phase: WaitPhase,
+    timeout: Duration,
+    active_low: bool,
+}
+
+impl BusyWait {
+    /// Create a wait during a phase that gives up after a timeout, for a controller that
+    /// holds the busy line high while it is working
+    pub fn new(phase: WaitPhase, timeout: Duration) -> Self {
+        Self {
+            phase,
+            timeout,
+            active_low: false,
+        }
+    }
+
+    /// Set whether the controller holds the busy line low rather than high while it is working
+    pub fn with_active_low(mut self, active_low: bool) -> Self {
+        self.active_low = active_low;
+        self
+    }
+
+    /// Get whether the controller holds the busy line low while it is working
+    pub fn active_low(&self) -> bool {
+        self.active_low
+    }
+
+    /// Get the phase the wait happens in
+    pub fn phase(&self) -> WaitPhase {
+        self.phase
+    }
+
+    /// Get how long to wait before giving up
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Get the error reported when the wait gives up after `waited`
+    pub fn timed_out(&self, waited: Duration) -> BusyTimeout {
+        BusyTimeout {
+            phase: self.phase,
+            waited,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Error returned when the busy line is not released in time, usually because the panel is
+/// disconnected or stuck. Transports return it through `anyhow`, so it can be recovered with
+/// `downcast_ref`.
+pub struct BusyTimeout {
+    phase: WaitPhase,
+    waited: Duration,
+}
+
+impl BusyTimeout {
+    /// Get the phase that was waiting
+    pub fn phase(&self) -> WaitPhase {
+        self.phase
+    }
+
+    /// Get how long the transport waited before giving up
+    pub fn waited(&self) -> Duration {
+        self.waited
+    }
+}
+
+impl Display for BusyTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Busy line was not released during {} after waiting {:.2?}",
+            self.phase, self.waited
+        )
+    }
+}
+
+impl Error for BusyTimeout {}
+
+/// The operations needed to drive an Inky display controller
+pub trait DisplayTransport {
+    /// Send a command byte to the controller with the DC line held low
+    fn send_command(&mut self, command: u8) -> Result<()>;
+
+    /// Send data bytes to the controller with the DC line held high
+    fn send_data(&mut self, data: &[u8]) -> Result<()>;
+
+    /// Read data bytes from the controller with the DC line held high, after a command asking
+    /// for them. Transports with no data line from the controller fail
+    fn read_data(&mut self, _len: usize) -> Result<Vec<u8>> {
+        bail!("The transport cannot read from the controller")
+    }
+
+    /// Get whether `read_data` can read from the controller
+    fn can_read(&self) -> bool {
+        false
+    }
+
+    /// Send a command byte followed by its data bytes, either of which may be empty.
+    /// Transports that drive the chip select line themselves hold it for the whole packet
+    fn send_packet(&mut self, command: Option<u8>, data: &[u8]) -> Result<()> {
+        if let Some(command) = command {
+            self.send_command(command)?;
+        }
+        if !data.is_empty() {
+            self.send_data(data)?;
+        }
+        Ok(())
+    }
+
+    /// Get whether `send_packet` holds chip select low for a whole command and its data, which
+    /// some controllers need
+    fn holds_cs(&self) -> bool {
+        false
+    }
+
+    /// Pulse the hardware reset line of the controller
+    fn reset(&mut self) -> Result<()>;
+
+    /// Block until the controller releases the busy line, failing with [`BusyTimeout`] if it
+    /// is not released within the timeout of the wait
+    fn wait(&mut self, wait: &BusyWait) -> Result<()>;
+
+    /// Pause for a fixed duration
+    fn delay(&mut self, duration: Duration) {
+        sleep(duration);
+    }
+}
+
+/// A borrowed transport drives the display the same way, so the transport can be inspected or
+/// reused after the display is dropped
+impl<T: DisplayTransport + ?Sized> DisplayTransport for &mut T {
+    fn send_command(&mut self, command: u8) -> Result<()> {
+        (**self).send_command(command)
+    }
+
+    fn send_data(&mut self, data: &[u8]) -> Result<()> {
+        (**self).send_data(data)
+    }
+
+    fn read_data(&mut self, len: usize) -> Result<Vec<u8>> {
+        (**self).read_data(len)
+    }
+
+    fn can_read(&self) -> bool {
+        (**self).can_read()
+    }
+
+    fn send_packet(&mut self, command: Option<u8>, data: &[u8]) -> Result<()> {
+        (**self).send_packet(command, data)
+    }
+
+    fn holds_cs(&self) -> bool {
+        (**self).holds_cs()
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        (**self).reset()
+    }
+
+    fn wait(&mut self, wait: &BusyWait) -> Result<()> {
+        (**self).wait(wait)
+    }
+
+    fn delay(&mut self, duration: Duration) {
+        (**self).delay(duration)
+    }
+}
+
+// The largest transfer the spidev driver accepts by default
+const SPI_CHUNK_SIZE: usize = 4096;
+// Pin hold time used when pulsing the reset line, from the inky library
+const RESET_HOLD: Duration = Duration::from_millis(100);
+
+/// Transport using the Raspberry Pi SPI bus and GPIO lines through rppal
+pub struct RppalTransport {
+    spi: Spi,
+    dc: OutputPin,
+    reset: OutputPin,
+    busy: InputPin,
+    cs: Option<OutputPin>,
+}
+
+impl RppalTransport {
+    // GPIO pin connected to the DC line
+    pub const DC_PIN: u8 = 22;
+    // GPIO pin connected to the reset line
+    pub const RESET_PIN: u8 = 27;
+    // GPIO pin connected to the busy line
+    pub const BUSY_PIN: u8 = 17;
+    // SPI clock speed used by the inky library
+    pub const SPI_CLOCK_HZ: u32 = 488_000;
+
+    /// Open the SPI bus and claim the GPIO pins the Inky HAT is wired to
+    pub fn new() -> Result<Self> {
+        Self::with_config(&InkyConfig::default())
+    }
+
+    /// Open the SPI bus and claim the GPIO pins given in a configuration
+    pub fn with_config(config: &InkyConfig) -> Result<Self> {
+        let gpio = Gpio::new()?;
+
+        let dc = gpio.get(config.dc_pin())?.into_output_low();
+        let reset = gpio.get(config.reset_pin())?.into_output_high();
+        let busy = gpio.get(config.busy_pin())?.into_input();
+        // Taking the pin as a GPIO output stops the SPI controller from driving it
+        let cs = match config.cs_pin() {
+            Some(pin) => Some(gpio.get(pin)?.into_output_high()),
+            None => None,
+        };
+
+        Ok(Self {
+            spi: Spi::new(
+                config.spi_bus()?,
+                config.chip_select()?,
+                config.spi_clock_hz(),
+                Mode::Mode0,
+            )?,
+            dc,
+            reset,
+            busy,
+            cs,
+        })
+    }
+}
+
+impl DisplayTransport for RppalTransport {
+    fn send_command(&mut self, command: u8) -> Result<()> {
+        self.dc.set_low();
+        self.spi.write(&[command])?;
+        Ok(())
+    }
+
+    fn send_data(&mut self, data: &[u8]) -> Result<()> {
+        self.dc.set_high();
+        for chunk in data.chunks(SPI_CHUNK_SIZE) {
+            self.spi.write(chunk)?;
+        }
+        Ok(())
+    }
+
+    fn send_packet(&mut self, command: Option<u8>, data: &[u8]) -> Result<()> {
+        if let Some(cs) = self.cs.as_mut() {
+            cs.set_low();
+        }
+        if let Some(command) = command {
+            self.send_command(command)?;
+        }
+        if !data.is_empty() {
+            self.send_data(data)?;
+        }
+        if let Some(cs) = self.cs.as_mut() {
+            cs.set_high();
+        }
+        Ok(())
+    }
+
+    fn holds_cs(&self) -> bool {
+        self.cs.is_some()
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.reset.set_low();
+        sleep(RESET_HOLD);
+        self.reset.set_high();
+        sleep(RESET_HOLD);
+        Ok(())
+    }
+
+    fn wait(&mut self, wait: &BusyWait) -> Result<()> {
+        let start = Instant::now();
+        let trigger = if wait.active_low() {
+            Trigger::RisingEdge
+        } else {
+            Trigger::FallingEdge
+        };
+        // Arm the interrupt before reading the level, so a release in between is not missed.
+        // The controller may already be done, in which case there is no edge to wait for
+        self.busy.set_interrupt(trigger)?;
+        let done = self.busy.is_high() == wait.active_low();
+        let released = done
+            || self
+                .busy
+                .poll_interrupt(false, Some(wait.timeout()))?
+                .is_some();
+        self.busy.clear_interrupt()?;
+        if !released {
+            return Err(wait.timed_out(start.elapsed()).into());
+        }
+        Ok(())
+    }
+}
+
+/// Transport using `embedded-hal` 1.0 implementations of the SPI bus, GPIO pins and a delay
+/// source, for boards other than the Raspberry Pi. `SpiDevice` releases chip select between
+/// the command and data writes, so displays that need it held, like the AC073TC1A, are refused.
+/// Inky boards have no data line back from the controller, so reading is off unless enabled
+/// with [`HalTransport::with_read`]
+pub struct HalTransport<SPI, DC, RST, BUSY, D> {
+    spi: SPI,
+    dc: DC,
+    reset: RST,
+    busy: BUSY,
+    delay: D,
+    read: bool,
+}
+
+impl<SPI, DC, RST, BUSY, D> HalTransport<SPI, DC, RST, BUSY, D>
+where
+    SPI: SpiDevice,
+    DC: HalOutputPin,
+    RST: HalOutputPin,
+    BUSY: HalInputPin,
+    D: DelayNs,
+{
+    // How often the busy line is polled while waiting for the controller
+    pub const BUSY_POLL_MS: u32 = 10;
+
+    /// Create a transport from an SPI device, the DC, reset and busy pins, and a delay source
+    pub fn new(spi: SPI, dc: DC, reset: RST, busy: BUSY, delay: D) -> Self {
+        Self {
+            spi,
+            dc,
+            reset,
+            busy,
+            delay,
+            read: false,
+        }
+    }
+
+    /// Set whether the board wires the controller's data line back to the SPI bus, so
+    /// `read_data` can read from it
+    pub fn with_read(mut self, read: bool) -> Self {
+        self.read = read;
+        self
+    }
+
+    /// Give the bus, pins and delay source back
+    pub fn release(self) -> (SPI, DC, RST, BUSY, D) {
+        (self.spi, self.dc, self.reset, self.busy, self.delay)
+    }
+}
+
+impl<SPI, DC, RST, BUSY, D> DisplayTransport for HalTransport<SPI, DC, RST, BUSY, D>
+where
+    SPI: SpiDevice,
+    DC: HalOutputPin,
+    RST: HalOutputPin,
+    BUSY: HalInputPin,
+    D: DelayNs,
+{
+    fn send_command(&mut self, command: u8) -> Result<()> {
+        self.dc
+            .set_low()
+            .map_err(|e| anyhow!("Failed to set DC pin: {:?}", e))?;
+        self.spi
+            .write(&[command])
+            .map_err(|e| anyhow!("Failed to write command: {:?}", e))
+    }
+
+    fn send_data(&mut self, data: &[u8]) -> Result<()> {
+        self.dc
+            .set_high()
+            .map_err(|e| anyhow!("Failed to set DC pin: {:?}", e))?;
+        for chunk in data.chunks(SPI_CHUNK_SIZE) {
+            self.spi
+                .write(chunk)
+                .map_err(|e| anyhow!("Failed to write data: {:?}", e))?;
+        }
+        Ok(())
+    }
+
+    fn read_data(&mut self, len: usize) -> Result<Vec<u8>> {
+        ensure!(self.read, "The transport cannot read from the controller");
+        self.dc
+            .set_high()
+            .map_err(|e| anyhow!("Failed to set DC pin: {:?}", e))?;
+        let mut data = vec![0; len];
+        self.spi
+            .read(&mut data)
+            .map_err(|e| anyhow!("Failed to read data: {:?}", e))?;
+        Ok(data)
+    }
+
+    fn can_read(&self) -> bool {
+        self.read
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.reset
+            .set_low()
+            .map_err(|e| anyhow!("Failed to set reset pin: {:?}", e))?;
+        self.delay(RESET_HOLD);
+        self.reset
+            .set_high()
+            .map_err(|e| anyhow!("Failed to set reset pin: {:?}", e))?;
+        self.delay(RESET_HOLD);
+        Ok(())
+    }
+
+    fn wait(&mut self, wait: &BusyWait) -> Result<()> {
+        // Time is counted in polls, since the delay source is the only clock available
+        let mut waited = Duration::ZERO;
+        while self
+            .busy
+            .is_high()
+            .map_err(|e| anyhow!("Failed to read busy pin: {:?}", e))?
+            != wait.active_low()
+        {
+            if waited >= wait.timeout() {
+                return Err(wait.timed_out(waited).into());
+            }
+            self.delay.delay_ms(Self::BUSY_POLL_MS);
+            waited += Duration::from_millis(Self::BUSY_POLL_MS.into());
+        }
+        Ok(())
+    }
+
+    fn delay(&mut self, duration: Duration) {
+        // A u32 of microseconds lasts a little over an hour, so longer pauses take several
+        let mut remaining = duration.as_micros();
+        while remaining > 0 {
+            let us = u32::try_from(remaining).unwrap_or(u32::MAX);
+            self.delay.delay_us(us);
+            remaining -= u128::from(us);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BusyTimeout, BusyWait, DisplayTransport, HalTransport, WaitPhase};
+    use crate::{
+        eeprom::{DisplayModel, EEPROM},
+        inky::Inky,
+    };
+    use anyhow::Result;
+    use embedded_hal_mock::eh1::{
+        delay::{CheckedDelay, NoopDelay, Transaction as DelayTransaction},
+        digital::{Mock as PinMock, State, Transaction as PinTransaction},
+        spi::{Mock as SpiMock, Transaction as SpiTransaction},
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn test_hal_transport() -> Result<()> {
+        let spi = SpiMock::new(&[
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write_vec(vec![0x01]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write_vec(vec![0x2c, 0x01, 0x00]),
+            SpiTransaction::transaction_end(),
+        ]);
+        let dc = PinMock::new(&[
+            PinTransaction::set(State::Low),
+            PinTransaction::set(State::High),
+        ]);
+        let reset = PinMock::new(&[
+            PinTransaction::set(State::Low),
+            PinTransaction::set(State::High),
+        ]);
+        let busy = PinMock::new(&[
+            PinTransaction::get(State::High),
+            PinTransaction::get(State::High),
+            PinTransaction::get(State::Low),
+        ]);
+
+        let mut transport = HalTransport::new(spi, dc, reset, busy, NoopDelay::new());
+        transport.reset()?;
+        transport.send_command(0x01)?;
+        transport.send_data(&[0x2c, 0x01, 0x00])?;
+        transport.wait(&BusyWait::new(WaitPhase::Reset, Duration::from_secs(1)))?;
+
+        let (mut spi, mut dc, mut reset, mut busy, _) = transport.release();
+        spi.done();
+        dc.done();
+        reset.done();
+        busy.done();
+        Ok(())
+    }
+
+    #[test]
+    fn test_hal_transport_long_delay() {
+        let mut spi = SpiMock::new(&[]);
+        let mut dc = PinMock::new(&[]);
+        let mut reset = PinMock::new(&[]);
+        let mut busy = PinMock::new(&[]);
+        // 5000s is more microseconds than a u32 holds, so it is split in two
+        let mut delay = CheckedDelay::new(&[
+            DelayTransaction::delay_us(u32::MAX),
+            DelayTransaction::delay_us(705_032_705),
+        ]);
+        let mut transport = HalTransport::new(
+            spi.clone(),
+            dc.clone(),
+            reset.clone(),
+            busy.clone(),
+            delay.clone(),
+        );
+        transport.delay(Duration::from_secs(5000));
+
+        spi.done();
+        dc.done();
+        reset.done();
+        busy.done();
+        delay.done();
+    }
+
+    #[test]
+    fn test_hal_transport_read() -> Result<()> {
+        let mut spi = SpiMock::new(&[
+            SpiTransaction::transaction_start(),
+            SpiTransaction::read_vec(vec![0x19, 0x00]),
+            SpiTransaction::transaction_end(),
+        ]);
+        let mut dc = PinMock::new(&[PinTransaction::set(State::High)]);
+        let mut reset = PinMock::new(&[]);
+        let mut busy = PinMock::new(&[]);
+        let mut transport = HalTransport::new(
+            spi.clone(),
+            dc.clone(),
+            reset.clone(),
+            busy.clone(),
+            NoopDelay::new(),
+        );
+
+        // Reading is refused until the board says it has a data line back
+        assert!(!transport.can_read());
+        assert!(transport.read_data(2).is_err());
+
+        let mut transport = transport.with_read(true);
+        assert!(transport.can_read());
+        assert_eq!(transport.read_data(2)?, vec![0x19, 0x00]);
+
+        spi.done();
+        dc.done();
+        reset.done();
+        busy.done();
+        Ok(())
+    }
+
+    #[test]
+    fn test_hal_transport_rejects_held_cs() {
+        let mut spi = SpiMock::new(&[]);
+        let mut dc = PinMock::new(&[]);
+        let mut reset = PinMock::new(&[]);
+        let mut busy = PinMock::new(&[]);
+        let transport = HalTransport::new(
+            spi.clone(),
+            dc.clone(),
+            reset.clone(),
+            busy.clone(),
+            NoopDelay::new(),
+        );
+
+        // The AC073TC1A is refused before anything is sent, as chip select drops between the
+        // command and its data
+        let error = Inky::with_transport(
+            EEPROM::for_model(DisplayModel::SevenColor800x480Ac073Tc1A),
+            transport,
+        )
+        .err()
+        .expect("AC073TC1A was driven over a transport that cannot hold chip select");
+        assert!(error.to_string().contains("chip select"));
+
+        spi.done();
+        dc.done();
+        reset.done();
+        busy.done();
+    }
+
+    #[test]
+    fn test_hal_transport_busy_timeout() {
+        let spi = SpiMock::new(&[]);
+        let dc = PinMock::new(&[]);
+        let reset = PinMock::new(&[]);
+        // A busy line that never falls, read once per 10ms poll until the 50ms timeout
+        let busy = PinMock::new(&vec![PinTransaction::get(State::High); 6]);
+
+        let mut transport = HalTransport::new(spi, dc, reset, busy, NoopDelay::new());
+        let error = transport
+            .wait(&BusyWait::new(WaitPhase::Update, Duration::from_millis(50)))
+            .expect_err("Wait on a stuck busy line succeeded");
+        let timeout = error
+            .downcast_ref::<BusyTimeout>()
+            .expect("Wait failed with an error other than a timeout");
+        assert_eq!(timeout.phase(), WaitPhase::Update);
+        assert_eq!(timeout.waited(), Duration::from_millis(50));
+
+        let (mut spi, mut dc, mut reset, mut busy, _) = transport.release();
+        spi.done();
+        dc.done();
+        reset.done();
+        busy.done();
+    }
+
+    #[test]
+    fn test_hal_transport_busy_active_low() -> Result<()> {
+        let spi = SpiMock::new(&[]);
+        let dc = PinMock::new(&[]);
+        let reset = PinMock::new(&[]);
+        // A controller that holds the busy line low for one poll, then releases it
+        let busy = PinMock::new(&[
+            PinTransaction::get(State::Low),
+            PinTransaction::get(State::High),
+        ]);
+
+        let mut transport = HalTransport::new(spi, dc, reset, busy, NoopDelay::new());
+        transport.wait(
+            &BusyWait::new(WaitPhase::PowerOn, Duration::from_millis(50)).with_active_low(true),
+        )?;
+
+        let (mut spi, mut dc, mut reset, mut busy, _) = transport.release();
+        spi.done();
+        dc.done();
+        reset.done();
+        busy.done();
+        Ok(())
+    }
+}